@@ -0,0 +1,254 @@
+//! ACPI module - interrupt controller discovery through the ACPI tables
+//!
+//! The root system description pointer (RSDP) is searched in the BIOS memory areas, then the root
+//! table is walked to find the multiple APIC description table (MADT), which reports the local APIC,
+//! the I/O APICs and how the ISA IRQs are connected to them. The tables are read through the
+//! physical memory mapping, so [`memory::init`] must have been called first.
+//!
+//! [`memory::init`]: crate::memory::init
+
+use core::ops::Range;
+use core::ptr;
+
+use spin::Once;
+use x86_64::PhysAddr;
+
+use crate::memory;
+
+/// Signature of the root system description pointer
+const RSDP_SIGNATURE: [u8; 8] = *b"RSD PTR ";
+/// Signature of the multiple APIC description table
+const MADT_SIGNATURE: [u8; 4] = *b"APIC";
+
+/// Physical address of the BIOS data area word that holds the segment of the extended BIOS data
+/// area
+const EBDA_SEGMENT: u64 = 0x40e;
+/// Number of bytes at the start of the extended BIOS data area that may hold the RSDP
+const EBDA_SEARCH_LEN: u64 = 1024;
+/// BIOS read-only memory area that may hold the RSDP
+const BIOS_AREA: Range<u64> = 0xe_0000..0x10_0000;
+
+/// Length of the ACPI 1.0 part of the RSDP, covered by its first checksum
+const RSDP_V1_LEN: usize = 20;
+/// Offset of the RSDT address in the RSDP
+const RSDP_RSDT: u64 = 16;
+/// Offset of the length of the RSDP, from ACPI 2.0
+const RSDP_LENGTH: u64 = 20;
+/// Offset of the XSDT address in the RSDP, from ACPI 2.0
+const RSDP_XSDT: u64 = 24;
+/// Offset of the revision in the RSDP
+const RSDP_REVISION: u64 = 15;
+
+/// Length of the header of the system description tables
+const SDT_HEADER_LEN: u64 = 36;
+/// Offset of the table length in the header of the system description tables
+const SDT_LENGTH: u64 = 4;
+
+/// Offset of the local APIC address in the MADT
+const MADT_LOCAL_APIC: u64 = 36;
+/// Offset of the first interrupt controller structure in the MADT
+const MADT_ENTRIES: u64 = 44;
+/// MADT structure type of an I/O APIC
+const MADT_IO_APIC: u8 = 1;
+/// MADT structure type of an interrupt source override
+const MADT_INTERRUPT_OVERRIDE: u8 = 2;
+
+/// Bits of the interrupt source override flags that encode the polarity
+const POLARITY_MASK: u16 = 0b11;
+/// Active low polarity
+const POLARITY_ACTIVE_LOW: u16 = 0b11;
+/// Bits of the interrupt source override flags that encode the trigger mode
+const TRIGGER_MASK: u16 = 0b1100;
+/// Level-triggered mode
+const TRIGGER_LEVEL: u16 = 0b1100;
+
+/// Maximum number of interrupt source overrides kept from the MADT
+pub const MAX_OVERRIDES: usize = 16;
+
+/// MADT of the machine, once [`madt`] has found it
+static MADT: Once<Madt> = Once::new();
+
+/// Errors that can occur when reading the ACPI tables
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AcpiError {
+    /// The physical memory is not mapped yet
+    MemoryUnavailable,
+    /// No valid RSDP was found in the BIOS memory areas
+    RsdpNotFound,
+    /// The root table doesn't list a valid MADT
+    MadtNotFound,
+    /// The MADT doesn't report an I/O APIC for the ISA IRQs
+    IoApicNotFound,
+}
+
+/// An I/O APIC reported by the MADT
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoApicInfo {
+    /// ID of the I/O APIC
+    pub id: u8,
+    /// Physical address of the I/O APIC registers
+    pub address: PhysAddr,
+    /// First global system interrupt handled by the I/O APIC
+    pub gsi_base: u32,
+}
+
+/// An interrupt source override, which connects an ISA IRQ to another global system interrupt, or
+/// with another polarity or trigger mode than the ISA default (active high, edge-triggered)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InterruptOverride {
+    /// ISA IRQ
+    pub irq: u8,
+    /// Global system interrupt the IRQ is connected to
+    pub gsi: u32,
+    /// `true` if the interrupt is active low
+    pub active_low: bool,
+    /// `true` if the interrupt is level-triggered
+    pub level_triggered: bool,
+}
+
+/// Interrupt controllers reported by the multiple APIC description table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Madt {
+    /// Physical address of the local APIC registers
+    pub local_apic: PhysAddr,
+    /// I/O APIC that handles global system interrupt 0, where the ISA IRQs are connected
+    pub io_apic: IoApicInfo,
+    overrides: [Option<InterruptOverride>; MAX_OVERRIDES],
+}
+
+impl Madt {
+    /// Return the interrupt source overrides
+    pub fn overrides(&self) -> impl Iterator<Item = &InterruptOverride> {
+        self.overrides.iter().flatten()
+    }
+
+    /// Return the interrupt source override of the passed ISA IRQ, if any
+    #[must_use]
+    pub fn isa_override(&self, irq: u8) -> Option<InterruptOverride> {
+        self.overrides().find(|o| o.irq == irq).copied()
+    }
+
+    /// Parse the MADT at the passed physical address, whose checksum has been verified
+    fn parse(addr: u64) -> Result<Self, AcpiError> {
+        let end = addr + u64::from(read::<u32>(addr + SDT_LENGTH)?);
+        let mut io_apic = None;
+        let mut overrides = [None; MAX_OVERRIDES];
+        let mut len = 0;
+
+        let mut entry = addr + MADT_ENTRIES;
+        while entry + 2 <= end {
+            let entry_len = u64::from(read::<u8>(entry + 1)?);
+            if entry_len < 2 || entry + entry_len > end {
+                break;
+            }
+            match read::<u8>(entry)? {
+                MADT_IO_APIC if entry_len >= 12 => {
+                    let info = IoApicInfo {
+                        id: read(entry + 2)?,
+                        address: PhysAddr::new(u64::from(read::<u32>(entry + 4)?)),
+                        gsi_base: read(entry + 8)?,
+                    };
+                    if info.gsi_base == 0 {
+                        io_apic = Some(info);
+                    }
+                }
+                // There can't be more overrides than ISA IRQs, so extra ones are bogus and dropped
+                MADT_INTERRUPT_OVERRIDE if entry_len >= 10 && len < MAX_OVERRIDES => {
+                    let flags: u16 = read(entry + 8)?;
+                    overrides[len] = Some(InterruptOverride {
+                        irq: read(entry + 3)?,
+                        gsi: read(entry + 4)?,
+                        active_low: flags & POLARITY_MASK == POLARITY_ACTIVE_LOW,
+                        level_triggered: flags & TRIGGER_MASK == TRIGGER_LEVEL,
+                    });
+                    len += 1;
+                }
+                _ => {}
+            }
+            entry += entry_len;
+        }
+
+        Ok(Self {
+            local_apic: PhysAddr::new(u64::from(read::<u32>(addr + MADT_LOCAL_APIC)?)),
+            io_apic: io_apic.ok_or(AcpiError::IoApicNotFound)?,
+            overrides,
+        })
+    }
+}
+
+/// Return the MADT of the machine, reading the ACPI tables on the first successful call.
+///
+/// ## Errors
+///
+/// Returns an [`AcpiError`] if the physical memory is not mapped yet, or if the ACPI tables or the
+/// MADT are missing or invalid.
+pub fn madt() -> Result<&'static Madt, AcpiError> {
+    MADT.try_call_once(|| {
+        let rsdp = find_rsdp()?;
+        let addr = find_table(rsdp, MADT_SIGNATURE)?.ok_or(AcpiError::MadtNotFound)?;
+        Madt::parse(addr)
+    })
+}
+
+/// Return the physical address of the RSDP, searching the first KiB of the extended BIOS data area,
+/// then the BIOS read-only memory area, on 16-byte boundaries
+fn find_rsdp() -> Result<u64, AcpiError> {
+    let ebda = u64::from(read::<u16>(EBDA_SEGMENT)?) << 4;
+
+    for area in [ebda..ebda + EBDA_SEARCH_LEN, BIOS_AREA] {
+        for addr in area.step_by(16) {
+            if read::<[u8; 8]>(addr)? == RSDP_SIGNATURE && checksum(addr, RSDP_V1_LEN)? {
+                return Ok(addr);
+            }
+        }
+    }
+    Err(AcpiError::RsdpNotFound)
+}
+
+/// Return the physical address of the table with the passed signature listed by the root table
+/// (the XSDT from ACPI 2.0, the RSDT otherwise), or `None` if there is no valid one
+fn find_table(rsdp: u64, signature: [u8; 4]) -> Result<Option<u64>, AcpiError> {
+    let xsdt = read::<u8>(rsdp + RSDP_REVISION)? >= 2
+        && checksum(rsdp, read::<u32>(rsdp + RSDP_LENGTH)? as usize)?;
+    let (root, entry_size) = if xsdt {
+        (read::<u64>(rsdp + RSDP_XSDT)?, 8)
+    } else {
+        (u64::from(read::<u32>(rsdp + RSDP_RSDT)?), 4)
+    };
+    if !valid_table(root)? {
+        return Ok(None);
+    }
+
+    let end = root + u64::from(read::<u32>(root + SDT_LENGTH)?);
+    for entry in (root + SDT_HEADER_LEN..end).step_by(entry_size) {
+        let table = if xsdt {
+            read::<u64>(entry)?
+        } else {
+            u64::from(read::<u32>(entry)?)
+        };
+        if read::<[u8; 4]>(table)? == signature && valid_table(table)? {
+            return Ok(Some(table));
+        }
+    }
+    Ok(None)
+}
+
+/// Return `true` if the system description table at the passed physical address has a valid length
+/// and checksum
+fn valid_table(addr: u64) -> Result<bool, AcpiError> {
+    let len = read::<u32>(addr + SDT_LENGTH)?;
+    Ok(u64::from(len) >= SDT_HEADER_LEN && checksum(addr, len as usize)?)
+}
+
+/// Return `true` if the `len` bytes at the passed physical address add up to zero
+fn checksum(addr: u64, len: usize) -> Result<bool, AcpiError> {
+    let offset = memory::physical_memory_offset().ok_or(AcpiError::MemoryUnavailable)?;
+    let bytes = unsafe { core::slice::from_raw_parts((offset + addr).as_ptr::<u8>(), len) };
+    Ok(bytes.iter().fold(0u8, |sum, &b| sum.wrapping_add(b)) == 0)
+}
+
+/// Read a value at the passed physical address, through the physical memory mapping
+fn read<T: Copy>(addr: u64) -> Result<T, AcpiError> {
+    let offset = memory::physical_memory_offset().ok_or(AcpiError::MemoryUnavailable)?;
+    Ok(unsafe { ptr::read_unaligned((offset + addr).as_ptr()) })
+}
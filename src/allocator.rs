@@ -1,55 +1,425 @@
 //! Allocator module
+//!
+//! The global allocator is a [`FixedSizeBlockAllocator`] by default. Enable the `bump_allocator` or
+//! the `linked_list_allocator` feature to use a [`BumpAllocator`] or a [`LinkedListAllocator`]
+//! instead (e.g. `cargo test --features bump_allocator --test heap_allocation`). Enable the `kasan`
+//! feature to wrap the global allocator in a [`Kasan`] debugging allocator, which catches heap buffer
+//! overflows and writes after free.
+//!
+//! [`FixedSizeBlockAllocator`]: fixed_size_block::FixedSizeBlockAllocator
+//! [`Kasan`]: kasan::Kasan
+//! [`BumpAllocator`]: bump::BumpAllocator
+//! [`LinkedListAllocator`]: linked_list::LinkedListAllocator
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::fmt;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
-use linked_list_allocator::LockedHeap;
 use x86_64::VirtAddr;
 use x86_64::structures::paging::mapper::MapToError;
-use x86_64::structures::paging::{FrameAllocator, Mapper, Page, PageTableFlags, Size4KiB};
+use x86_64::structures::paging::{
+    FrameAllocator, Mapper, Page, PageSize, PageTableFlags, Size2MiB, Size4KiB,
+};
+
+use crate::memory::region::{self, Backing, RegionError, RegionKind};
+use crate::memory::{KernelMemory, huge, protect};
+use crate::{memory, serial, vga_buffer};
 
 pub mod bump;
+pub mod fixed_size_block;
+pub mod kasan;
+pub mod linked_list;
+pub mod slab;
+
+#[cfg(all(feature = "bump_allocator", feature = "linked_list_allocator"))]
+compile_error!("the `bump_allocator` and `linked_list_allocator` features are mutually exclusive");
 
-/// Memory address where the heap starts
-pub const HEAP_START: usize = 0x0000_4444_4444_0000;
-/// Size of the heap in bytes
+/// Initial size of the heap in bytes
 pub const HEAP_SIZE: usize = 100 * 1024; // 100 KiB
+/// Maximum size the heap can grow to in bytes
+pub const HEAP_MAX_SIZE: usize = 32 * 1024 * 1024; // 32 MiB
+/// Minimum number of bytes the heap grows by
+const HEAP_GROWTH_STEP: usize = 64 * 1024; // 64 KiB
+/// Extra bytes the heap grows by, to leave room for alignment padding and allocator bookkeeping
+const HEAP_GROWTH_SLACK: usize = 64;
+
+/// Size of a heap page in bytes
+const PAGE_SIZE: usize = 4096;
+/// Size of a huge heap page in bytes
+#[allow(clippy::cast_possible_truncation)] // 2 MiB fits in a `usize`
+const HUGE_PAGE_SIZE: usize = Size2MiB::SIZE as usize;
+
+/// Memory address where the heap starts, allocated in the kernel region table by [`init_heap`]
+static HEAP_START: AtomicUsize = AtomicUsize::new(0);
+/// Number of bytes currently mapped for the heap
+static HEAP_MAPPED: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of bytes currently allocated
+static BYTES_ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+/// Highest number of bytes allocated at the same time
+static PEAK_BYTES_ALLOCATED: AtomicUsize = AtomicUsize::new(0);
+/// Number of successful allocations
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+/// Number of deallocations
+static DEALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
 
+/// Allocator type used as the global allocator
+#[cfg(feature = "bump_allocator")]
+type GlobalAllocator = bump::BumpAllocator;
+#[cfg(feature = "linked_list_allocator")]
+type GlobalAllocator = linked_list::LinkedListAllocator;
+#[cfg(not(any(feature = "bump_allocator", feature = "linked_list_allocator")))]
+type GlobalAllocator = fixed_size_block::FixedSizeBlockAllocator;
+
+#[cfg(not(feature = "kasan"))]
+#[global_allocator]
+static ALLOCATOR: Locked<GlobalAllocator> = Locked::new(GlobalAllocator::new());
+#[cfg(feature = "kasan")]
 #[global_allocator]
-static ALLOCATOR: LockedHeap = LockedHeap::empty();
+static ALLOCATOR: Locked<kasan::Kasan<GlobalAllocator>> =
+    Locked::new(kasan::Kasan::new(GlobalAllocator::new()));
+
+/// A wrapper around [`spin::Mutex`] that allows implementing traits such as [`GlobalAlloc`] on
+/// allocator types
+///
+/// [`GlobalAlloc`]: core::alloc::GlobalAlloc
+pub struct Locked<A> {
+    inner: spin::Mutex<A>,
+}
+
+impl<A> Locked<A> {
+    /// Wrap the passed allocator
+    pub const fn new(inner: A) -> Self {
+        Self {
+            inner: spin::Mutex::new(inner),
+        }
+    }
+
+    /// Lock the wrapped allocator
+    pub fn lock(&self) -> spin::MutexGuard<'_, A> {
+        self.inner.lock()
+    }
+}
+
+/// Heap allocator implementation, managing a heap that starts at a fixed address and can grow at
+/// its end
+pub trait HeapAllocator {
+    /// Initialize the allocator with the given heap bounds.
+    ///
+    /// ## Safety
+    ///
+    /// The caller must guarantee that the given memory range is unused. Also, this method must be
+    /// called only once.
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize);
+
+    /// Allocate memory for the passed layout, returning a null pointer if the heap is exhausted
+    fn allocate(&mut self, layout: Layout) -> *mut u8;
+
+    /// Deallocate memory previously returned by [`HeapAllocator::allocate`].
+    ///
+    /// ## Safety
+    ///
+    /// The caller must guarantee that `ptr` was allocated by this allocator with the same layout.
+    unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout);
+
+    /// Extend the heap by `size` bytes at its end.
+    ///
+    /// ## Safety
+    ///
+    /// The caller must guarantee that the `size` bytes after the current end of the heap are mapped
+    /// and unused.
+    unsafe fn extend(&mut self, size: usize);
+
+    /// Return the number of free bytes in the heap
+    fn free_bytes(&self) -> usize;
+
+    /// Return the size of the largest block that can be allocated without growing the heap
+    fn largest_free_block(&self) -> usize;
+}
+
+unsafe impl<A: HeapAllocator> GlobalAlloc for Locked<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let mut allocator = self.lock();
+
+        // If the heap is exhausted, try to grow it before giving up
+        let mut ptr = allocator.allocate(layout);
+        if ptr.is_null() && grow_heap(&mut *allocator, layout).is_some() {
+            ptr = allocator.allocate(layout);
+        }
+
+        if !ptr.is_null() {
+            let allocated = BYTES_ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+            PEAK_BYTES_ALLOCATED.fetch_max(allocated + layout.size(), Ordering::Relaxed);
+            ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        let mut allocator = self.lock();
+
+        unsafe { allocator.deallocate(ptr, layout) };
+        BYTES_ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        DEALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Heap usage statistics
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HeapStats {
+    /// Number of bytes currently allocated, as requested by the allocation layouts
+    pub bytes_allocated: usize,
+    /// Number of bytes currently free in the heap
+    pub bytes_free: usize,
+    /// Highest number of bytes allocated at the same time
+    pub peak_bytes_allocated: usize,
+    /// Number of successful allocations
+    pub allocations: usize,
+    /// Number of deallocations
+    pub deallocations: usize,
+    /// Size of the largest block that can be allocated without growing the heap
+    pub largest_free_block: usize,
+}
+
+/// Return the current [`HeapStats`] of the global allocator
+pub fn stats() -> HeapStats {
+    let allocator = ALLOCATOR.lock();
+
+    HeapStats {
+        bytes_allocated: BYTES_ALLOCATED.load(Ordering::Relaxed),
+        bytes_free: allocator.free_bytes(),
+        peak_bytes_allocated: PEAK_BYTES_ALLOCATED.load(Ordering::Relaxed),
+        allocations: ALLOCATIONS.load(Ordering::Relaxed),
+        deallocations: DEALLOCATIONS.load(Ordering::Relaxed),
+        largest_free_block: allocator.largest_free_block(),
+    }
+}
+
+/// Return the number of errors reported on serial by the [`Kasan`] global allocator
+#[cfg(feature = "kasan")]
+pub fn kasan_reports() -> usize {
+    ALLOCATOR.lock().reports()
+}
+
+/// Return the last error reported on serial by the [`Kasan`] global allocator, if any
+#[cfg(feature = "kasan")]
+pub fn kasan_last_report() -> Option<kasan::Report> {
+    ALLOCATOR.lock().last_report()
+}
+
+/// Panic if the number of bytes allocated differs from the one in the passed [`HeapStats`] snapshot,
+/// which tests can take with [`stats`] before running a test case to check that it didn't leak
+///
+/// ## Panics
+///
+/// Panics if heap bytes have been leaked (or freed) since the snapshot was taken.
+#[track_caller]
+pub fn assert_no_leaks(before: &HeapStats) {
+    let after = stats();
+    assert_eq!(
+        after.bytes_allocated,
+        before.bytes_allocated,
+        "heap leak: {} allocations and {} deallocations since the snapshot",
+        after.allocations - before.allocations,
+        after.deallocations - before.deallocations
+    );
+}
+
+/// Report heap exhaustion, i.e. an allocation that failed even after trying to grow the heap, on
+/// both the VGA buffer and the serial port, then panic through the panic handler
+#[alloc_error_handler]
+fn alloc_error_handler(layout: Layout) -> ! {
+    let stats = stats();
+    let heap_start = heap_start();
+    let heap_end = heap_start + heap_size();
+
+    for print in [
+        vga_buffer::print_helper as fn(fmt::Arguments),
+        serial::print_helper,
+    ] {
+        print(format_args!(
+            "\nOUT OF MEMORY: failed to allocate {layout:?}\n\
+             heap: {heap_start:#x}..{heap_end:#x} ({} of {HEAP_MAX_SIZE} bytes mapped, initially {HEAP_SIZE})\n\
+             usage: {} bytes allocated, {} bytes free, largest free block {} bytes, peak {} bytes\n",
+            heap_size(),
+            stats.bytes_allocated,
+            stats.bytes_free,
+            stats.largest_free_block,
+            stats.peak_bytes_allocated,
+        ));
+    }
+
+    panic!(
+        "heap exhausted: allocation of {} bytes failed",
+        layout.size()
+    );
+}
+
+/// Align the passed address upwards to the passed alignment, which must be a power of two
+const fn align_up(addr: usize, align: usize) -> usize {
+    (addr + align - 1) & !(align - 1)
+}
+
+/// Return the address where the heap starts, or 0 if the heap has not been initialized
+pub fn heap_start() -> usize {
+    HEAP_START.load(Ordering::Relaxed)
+}
+
+/// Return the number of bytes currently mapped for the heap
+pub fn heap_size() -> usize {
+    HEAP_MAPPED.load(Ordering::Relaxed)
+}
+
+/// Heap initialization errors
+#[derive(Debug)]
+pub enum HeapError {
+    /// The heap address range could not be allocated in the kernel region table
+    Region(RegionError),
+    /// Mapping the initial heap pages failed
+    Map(MapToError<Size4KiB>),
+}
+
+impl From<RegionError> for HeapError {
+    fn from(err: RegionError) -> Self {
+        Self::Region(err)
+    }
+}
+
+impl From<MapToError<Size4KiB>> for HeapError {
+    fn from(err: MapToError<Size4KiB>) -> Self {
+        Self::Map(err)
+    }
+}
 
 /// Initialize the heap based on a [`Mapper`] and a [`FrameAllocator`] instance (both limited to
 /// 4 KiB pages).
 ///
+/// The heap address range is allocated in the kernel region table, aligned to 2 MiB. Once the kernel
+/// memory has been initialized with [`memory::init_kernel_memory`], the heap grows on demand up to
+/// [`HEAP_MAX_SIZE`]. Growth that starts on a 2 MiB boundary is mapped with 2 MiB pages when the
+/// frame allocator has an aligned 2 MiB block left, and with 4 KiB pages otherwise.
+///
 /// ## Errors
 ///
-/// Returns a [`MapToError`] in case [`Mapper::map_to`] fails.
+/// Returns a [`HeapError`] if the heap address range can't be allocated, or if
+/// [`Mapper::map_to`] fails.
 pub fn init_heap(
     mapper: &mut impl Mapper<Size4KiB>,
     frame_allocator: &mut impl FrameAllocator<Size4KiB>,
-) -> Result<(), MapToError<Size4KiB>> {
-    // Create the page range
-    let page_range = {
-        let heap_start = VirtAddr::new(HEAP_START as u64);
-        let heap_end = heap_start + HEAP_SIZE as u64 - 1;
-
-        let heap_start_page = Page::containing_address(heap_start);
-        let heap_end_page = Page::containing_address(heap_end);
-
-        Page::range_inclusive(heap_start_page, heap_end_page)
-    };
+) -> Result<(), HeapError> {
+    // Allocate the address range the heap can grow into
+    let size = HEAP_MAX_SIZE as u64;
+    let region = region::allocate(
+        "heap",
+        size,
+        Size2MiB::SIZE,
+        RegionKind::Heap,
+        Backing::Eager,
+    )?;
+    #[allow(clippy::cast_possible_truncation)] // Kernel addresses fit in a `usize`
+    HEAP_START.store(region.start.as_u64() as usize, Ordering::Relaxed);
 
     // Map all heap pages to physical frames
-    for page in page_range {
-        let frame = frame_allocator
-            .allocate_frame()
-            .ok_or(MapToError::FrameAllocationFailed)?;
-        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
-
-        unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
+    for page in heap_pages(0, HEAP_SIZE) {
+        map_heap_page(page, mapper, frame_allocator)?;
     }
 
     // Initialize the allocator
     unsafe {
-        ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
+        ALLOCATOR.lock().init(heap_start(), HEAP_SIZE);
+    }
+    HEAP_MAPPED.store(HEAP_SIZE, Ordering::Relaxed);
+
+    Ok(())
+}
+
+/// Map more pages at the end of the heap, so that an allocation with the passed layout can succeed.
+/// Return `None` if the heap can't grow enough without exceeding [`HEAP_MAX_SIZE`], or if the kernel
+/// memory is not available.
+fn grow_heap(allocator: &mut impl HeapAllocator, layout: Layout) -> Option<()> {
+    let heap_size = heap_size();
+
+    let needed = layout
+        .size()
+        .checked_add(layout.align() + HEAP_GROWTH_SLACK)?;
+    if heap_size == 0 || needed > HEAP_MAX_SIZE - heap_size {
+        return None;
+    }
+    let grow_by = needed
+        .next_multiple_of(HEAP_GROWTH_STEP)
+        .min(HEAP_MAX_SIZE - heap_size);
+
+    // Map as many pages as possible, so that the mapped pages always match the heap size
+    let grown = memory::with_kernel_memory(|memory| {
+        let mut grown = 0;
+        while grown < grow_by {
+            match map_heap_chunk(heap_size + grown, memory) {
+                Some(size) => grown += size,
+                None => break,
+            }
+        }
+        grown
+    })?;
+    if grown == 0 {
+        return None;
     }
 
+    unsafe { allocator.extend(grown) };
+    HEAP_MAPPED.store(heap_size + grown, Ordering::Relaxed);
+
+    Some(())
+}
+
+/// Return the range of heap pages that starts at the passed offset from the heap start
+fn heap_pages(offset: usize, size: usize) -> impl Iterator<Item = Page> {
+    let start = VirtAddr::new((heap_start() + offset) as u64);
+    let end = start + size as u64 - 1;
+
+    Page::range_inclusive(
+        Page::containing_address(start),
+        Page::containing_address(end),
+    )
+}
+
+/// Map the heap at the passed offset from the heap start with a 2 MiB page if it starts on a 2 MiB
+/// boundary and fits in [`HEAP_MAX_SIZE`], falling back to a 4 KiB page. Return the number of bytes
+/// mapped, or `None` if no frame is left.
+fn map_heap_chunk(offset: usize, memory: &mut KernelMemory) -> Option<usize> {
+    let start = VirtAddr::new((heap_start() + offset) as u64);
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | protect::no_execute();
+
+    if start.is_aligned(Size2MiB::SIZE) && HEAP_MAX_SIZE - offset >= HUGE_PAGE_SIZE {
+        let page = Page::<Size2MiB>::containing_address(start);
+        let pages = Page::range(page, page + 1);
+        if huge::map_pages(
+            pages,
+            flags,
+            &mut memory.mapper,
+            &mut memory.frame_allocator,
+        )
+        .is_ok()
+        {
+            return Some(HUGE_PAGE_SIZE);
+        }
+    }
+
+    let page = Page::containing_address(start);
+    map_heap_page(page, &mut memory.mapper, &mut memory.frame_allocator).ok()?;
+    Some(PAGE_SIZE)
+}
+
+/// Map a heap page to a new physical frame
+fn map_heap_page(
+    page: Page,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameAllocator<Size4KiB>,
+) -> Result<(), MapToError<Size4KiB>> {
+    let frame = frame_allocator
+        .allocate_frame()
+        .ok_or(MapToError::FrameAllocationFailed)?;
+    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | protect::no_execute();
+
+    unsafe { mapper.map_to(page, frame, flags, frame_allocator)?.flush() };
+
     Ok(())
 }
@@ -1 +1,72 @@
 //! Bump allocator submodule
+
+use core::alloc::Layout;
+use core::ptr;
+
+use super::{HeapAllocator, align_up};
+
+/// A bump allocator that hands out memory linearly and only frees it when all allocations have
+/// been deallocated
+#[derive(Debug, Default)]
+pub struct BumpAllocator {
+    heap_start: usize,
+    heap_end: usize,
+    next: usize,
+    allocations: usize,
+}
+
+impl BumpAllocator {
+    /// Create a new empty [`BumpAllocator`]
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            heap_start: 0,
+            heap_end: 0,
+            next: 0,
+            allocations: 0,
+        }
+    }
+}
+
+impl HeapAllocator for BumpAllocator {
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        self.heap_start = heap_start;
+        self.heap_end = heap_start + heap_size;
+        self.next = heap_start;
+    }
+
+    fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        // Bump the next pointer past the aligned allocation, unless the heap is exhausted
+        let alloc_start = align_up(self.next, layout.align());
+        let Some(alloc_end) = alloc_start.checked_add(layout.size()) else {
+            return ptr::null_mut();
+        };
+        if alloc_end > self.heap_end {
+            return ptr::null_mut();
+        }
+
+        self.next = alloc_end;
+        self.allocations += 1;
+        alloc_start as *mut u8
+    }
+
+    unsafe fn deallocate(&mut self, _ptr: *mut u8, _layout: Layout) {
+        // Reset the heap once the last live allocation is gone
+        self.allocations -= 1;
+        if self.allocations == 0 {
+            self.next = self.heap_start;
+        }
+    }
+
+    unsafe fn extend(&mut self, size: usize) {
+        self.heap_end += size;
+    }
+
+    fn free_bytes(&self) -> usize {
+        self.heap_end - self.next
+    }
+
+    fn largest_free_block(&self) -> usize {
+        self.heap_end - self.next
+    }
+}
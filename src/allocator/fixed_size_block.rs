@@ -0,0 +1,117 @@
+//! Fixed-size block allocator submodule
+
+use core::alloc::Layout;
+use core::{iter, ptr};
+
+use super::HeapAllocator;
+use super::linked_list::LinkedListAllocator;
+
+/// Block sizes to use, which must be powers of two because they are also used as block alignment
+const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+
+/// Free list node, stored in each free block
+struct ListNode {
+    next: Option<&'static mut Self>,
+}
+
+/// An allocator that serves small allocations from per-size free lists of fixed-size blocks, and
+/// falls back to a linked list allocator for larger ones
+pub struct FixedSizeBlockAllocator {
+    list_heads: [Option<&'static mut ListNode>; BLOCK_SIZES.len()],
+    fallback_allocator: LinkedListAllocator,
+}
+
+impl Default for FixedSizeBlockAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FixedSizeBlockAllocator {
+    /// Create a new empty [`FixedSizeBlockAllocator`]
+    #[must_use]
+    pub const fn new() -> Self {
+        const EMPTY: Option<&'static mut ListNode> = None;
+        Self {
+            list_heads: [EMPTY; BLOCK_SIZES.len()],
+            fallback_allocator: LinkedListAllocator::new(),
+        }
+    }
+
+    /// Return the index of the block size to use for the passed layout, or `None` if the allocation
+    /// is too large for any block size
+    fn list_index(layout: &Layout) -> Option<usize> {
+        let required_block_size = layout.size().max(layout.align());
+        BLOCK_SIZES.iter().position(|&s| s >= required_block_size)
+    }
+
+    /// Return an iterator over the block sizes and the number of free blocks of each size
+    fn free_blocks(&self) -> impl Iterator<Item = (usize, usize)> {
+        self.list_heads
+            .iter()
+            .zip(BLOCK_SIZES)
+            .map(|(head, &size)| {
+                let count = iter::successors(head.as_deref(), |n| n.next.as_deref()).count();
+                (size, count)
+            })
+    }
+}
+
+impl HeapAllocator for FixedSizeBlockAllocator {
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        unsafe { self.fallback_allocator.init(heap_start, heap_size) };
+    }
+
+    fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let Some(index) = Self::list_index(&layout) else {
+            return self.fallback_allocator.allocate(layout);
+        };
+
+        // Pop a block from the free list, or carve a new one out of the fallback allocator
+        if let Some(node) = self.list_heads[index].take() {
+            self.list_heads[index] = node.next.take();
+            ptr::from_mut(node).cast()
+        } else {
+            let block_size = BLOCK_SIZES[index];
+            let block_layout = Layout::from_size_align(block_size, block_size)
+                .expect("block sizes are valid layouts");
+            self.fallback_allocator.allocate(block_layout)
+        }
+    }
+
+    unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        let Some(index) = Self::list_index(&layout) else {
+            unsafe { self.fallback_allocator.deallocate(ptr, layout) };
+            return;
+        };
+
+        // Push the block onto the free list (blocks are large and aligned enough to hold a node)
+        debug_assert!(size_of::<ListNode>() <= BLOCK_SIZES[index]);
+        debug_assert!(align_of::<ListNode>() <= BLOCK_SIZES[index]);
+        let new_node = ListNode {
+            next: self.list_heads[index].take(),
+        };
+        #[allow(clippy::cast_ptr_alignment)] // Blocks are aligned to their size
+        let new_node_ptr = ptr.cast::<ListNode>();
+        unsafe {
+            new_node_ptr.write(new_node);
+            self.list_heads[index] = Some(&mut *new_node_ptr);
+        }
+    }
+
+    unsafe fn extend(&mut self, size: usize) {
+        unsafe { self.fallback_allocator.extend(size) };
+    }
+
+    fn free_bytes(&self) -> usize {
+        let free_list_bytes: usize = self.free_blocks().map(|(size, count)| size * count).sum();
+        self.fallback_allocator.free_bytes() + free_list_bytes
+    }
+
+    fn largest_free_block(&self) -> usize {
+        self.free_blocks()
+            .filter(|&(_, count)| count > 0)
+            .map(|(size, _)| size)
+            .fold(self.fallback_allocator.largest_free_block(), usize::max)
+    }
+}
@@ -0,0 +1,214 @@
+//! Heap debugging (KASAN-lite) submodule
+
+use core::alloc::Layout;
+use core::{ptr, slice};
+
+use super::HeapAllocator;
+use crate::serial_println;
+
+/// Size of the redzones before and after each allocation in bytes
+pub const REDZONE_SIZE: usize = 16;
+/// Number of freed blocks kept in quarantine before they are given back to the inner allocator
+pub const QUARANTINE_LEN: usize = 64;
+
+/// Byte pattern that fills the redzones
+pub const REDZONE_BYTE: u8 = 0xfc;
+/// Byte pattern that fills freed memory
+pub const POISON_BYTE: u8 = 0x6b;
+
+/// A freed block waiting in quarantine
+#[derive(Debug, Clone, Copy)]
+struct Quarantined {
+    /// Start of the block returned to the caller
+    ptr: *mut u8,
+    /// Layout requested by the caller
+    layout: Layout,
+}
+
+/// An error reported by a [`Kasan`] allocator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Report {
+    /// Kind of error, e.g. `"heap buffer overflow"`
+    pub error: &'static str,
+    /// Address of the error, i.e. the first corrupted byte or the freed pointer
+    pub addr: *const u8,
+    /// Start of the allocation returned to the caller
+    pub ptr: *const u8,
+    /// Layout of the allocation
+    pub layout: Layout,
+}
+
+/// A debugging allocator that wraps another [`HeapAllocator`] to catch memory corruption.
+///
+/// Each allocation is surrounded by redzones filled with [`REDZONE_BYTE`], which are checked when
+/// the allocation is freed. Freed memory is filled with [`POISON_BYTE`] and kept in a quarantine of
+/// [`QUARANTINE_LEN`] blocks, so that it isn't reused right away and writes after free are detected
+/// when it leaves the quarantine. Errors are reported on serial, with the layout of the allocation.
+pub struct Kasan<A> {
+    inner: A,
+    quarantine: [Option<Quarantined>; QUARANTINE_LEN],
+    /// Index of the next quarantine slot, which holds the oldest block once the quarantine is full
+    next: usize,
+    reports: usize,
+    last_report: Option<Report>,
+}
+
+unsafe impl<A: Send> Send for Kasan<A> {}
+
+impl<A: HeapAllocator> Kasan<A> {
+    /// Create a new [`Kasan`] allocator that wraps the passed allocator
+    #[must_use]
+    pub const fn new(inner: A) -> Self {
+        Self {
+            inner,
+            quarantine: [None; QUARANTINE_LEN],
+            next: 0,
+            reports: 0,
+            last_report: None,
+        }
+    }
+
+    /// Return the number of errors reported so far
+    #[must_use]
+    pub const fn reports(&self) -> usize {
+        self.reports
+    }
+
+    /// Return the last error reported, if any
+    #[must_use]
+    pub const fn last_report(&self) -> Option<Report> {
+        self.last_report
+    }
+
+    /// Give all quarantined blocks back to the inner allocator, checking their poison first
+    pub fn flush_quarantine(&mut self) {
+        for index in 0..QUARANTINE_LEN {
+            self.evict(index);
+        }
+    }
+
+    /// Return the layout of the block that holds an allocation with the passed layout and its
+    /// redzones, and the offset of the allocation into it
+    fn outer_layout(layout: Layout) -> Option<(Layout, usize)> {
+        let offset = REDZONE_SIZE.next_multiple_of(layout.align());
+        let size = offset
+            .checked_add(layout.size())?
+            .checked_add(REDZONE_SIZE)?;
+        let outer = Layout::from_size_align(size, layout.align()).ok()?;
+        Some((outer, offset))
+    }
+
+    /// Give the quarantined block at the passed index back to the inner allocator, checking its
+    /// poison first
+    fn evict(&mut self, index: usize) {
+        let Some(block) = self.quarantine[index].take() else {
+            return;
+        };
+
+        let (outer, offset) = Self::outer_layout(block.layout).expect("layout was valid on alloc");
+        let start = block.ptr.wrapping_sub(offset);
+        let bytes = unsafe { slice::from_raw_parts(start, outer.size()) };
+        if let Some(i) = bytes.iter().position(|&b| b != POISON_BYTE) {
+            self.report("write after free", start.wrapping_add(i), &block);
+        }
+        unsafe { self.inner.deallocate(start, outer) };
+    }
+
+    /// Put the passed freed block in quarantine, evicting the oldest block if it is full
+    fn quarantine(&mut self, block: Quarantined) {
+        self.evict(self.next);
+        self.quarantine[self.next] = Some(block);
+        self.next = (self.next + 1) % QUARANTINE_LEN;
+    }
+
+    /// Check the redzones around the passed block, reporting the first corrupted byte
+    fn check_redzones(&mut self, block: &Quarantined, offset: usize) {
+        let before = unsafe { slice::from_raw_parts(block.ptr.wrapping_sub(offset), offset) };
+        let after_start = block.ptr.wrapping_add(block.layout.size());
+        let after = unsafe { slice::from_raw_parts(after_start, REDZONE_SIZE) };
+
+        if let Some(i) = before.iter().rposition(|&b| b != REDZONE_BYTE) {
+            let addr = block.ptr.wrapping_sub(offset - i);
+            self.report("heap buffer underflow", addr, block);
+        }
+        if let Some(i) = after.iter().position(|&b| b != REDZONE_BYTE) {
+            self.report("heap buffer overflow", after_start.wrapping_add(i), block);
+        }
+    }
+
+    /// Report an error at the passed address in the passed block on serial
+    fn report(&mut self, error: &'static str, addr: *const u8, block: &Quarantined) {
+        self.reports += 1;
+        self.last_report = Some(Report {
+            error,
+            addr,
+            ptr: block.ptr,
+            layout: block.layout,
+        });
+        serial_println!(
+            "KASAN: {} at {:p} in allocation {:p} with {:?}",
+            error,
+            addr,
+            block.ptr,
+            block.layout
+        );
+    }
+}
+
+impl<A: HeapAllocator> HeapAllocator for Kasan<A> {
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        unsafe { self.inner.init(heap_start, heap_size) };
+    }
+
+    fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let Some((outer, offset)) = Self::outer_layout(layout) else {
+            return ptr::null_mut();
+        };
+
+        // Quarantined blocks are given back before the heap is exhausted
+        let mut start = self.inner.allocate(outer);
+        if start.is_null() && self.quarantine.iter().any(Option::is_some) {
+            self.flush_quarantine();
+            start = self.inner.allocate(outer);
+        }
+        if start.is_null() {
+            return start;
+        }
+
+        unsafe {
+            start.write_bytes(REDZONE_BYTE, offset);
+            start
+                .add(offset + layout.size())
+                .write_bytes(REDZONE_BYTE, REDZONE_SIZE);
+            start.add(offset)
+        }
+    }
+
+    unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        let block = Quarantined { ptr, layout };
+        if self.quarantine.iter().flatten().any(|q| q.ptr == ptr) {
+            self.report("double free", ptr, &block);
+            return;
+        }
+        let Some((outer, offset)) = Self::outer_layout(layout) else {
+            self.report("invalid free", ptr, &block);
+            return;
+        };
+
+        self.check_redzones(&block, offset);
+        unsafe { ptr.sub(offset).write_bytes(POISON_BYTE, outer.size()) };
+        self.quarantine(block);
+    }
+
+    unsafe fn extend(&mut self, size: usize) {
+        unsafe { self.inner.extend(size) };
+    }
+
+    fn free_bytes(&self) -> usize {
+        self.inner.free_bytes()
+    }
+
+    fn largest_free_block(&self) -> usize {
+        self.inner.largest_free_block()
+    }
+}
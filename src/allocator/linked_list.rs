@@ -0,0 +1,197 @@
+//! Linked list allocator submodule
+
+use core::alloc::Layout;
+use core::iter;
+use core::ptr;
+
+use super::{HeapAllocator, align_up};
+
+/// Free list node, stored at the start of each free region
+struct ListNode {
+    size: usize,
+    next: Option<&'static mut Self>,
+}
+
+impl ListNode {
+    /// Create a new unlinked [`ListNode`]
+    const fn new(size: usize) -> Self {
+        Self { size, next: None }
+    }
+
+    /// Return the start address of the region
+    fn start_addr(&self) -> usize {
+        ptr::from_ref(self) as usize
+    }
+
+    /// Return the end address of the region
+    fn end_addr(&self) -> usize {
+        self.start_addr() + self.size
+    }
+}
+
+/// An allocator that keeps free regions in a linked list sorted by address, and merges adjacent
+/// free regions on deallocation
+pub struct LinkedListAllocator {
+    head: ListNode,
+    heap_end: usize,
+}
+
+impl Default for LinkedListAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LinkedListAllocator {
+    /// Create a new empty [`LinkedListAllocator`]
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            head: ListNode::new(0),
+            heap_end: 0,
+        }
+    }
+
+    /// Add the passed memory region to the free list, keeping it sorted by address and merging the
+    /// region with its neighbors if they are adjacent.
+    ///
+    /// ## Safety
+    ///
+    /// The caller must guarantee that the passed memory region is unused.
+    unsafe fn add_free_region(&mut self, addr: usize, size: usize) {
+        // Make sure that the free region is capable of holding a `ListNode`
+        assert_eq!(align_up(addr, align_of::<ListNode>()), addr);
+        assert!(size >= size_of::<ListNode>());
+
+        // Find the last region that starts before the new one
+        let mut current = &mut self.head;
+        while current.next.as_ref().is_some_and(|n| n.start_addr() < addr) {
+            current = current.next.as_mut().unwrap();
+        }
+
+        // Merge with the following region if adjacent
+        let mut node = ListNode::new(size);
+        node.next = current.next.take();
+        if let Some(next) = node.next.take_if(|n| addr + size == n.start_addr()) {
+            node.size += next.size;
+            node.next = next.next.take();
+        }
+
+        // Merge with the preceding region if adjacent (the dummy head has size 0 and is never merged)
+        if current.size > 0 && current.end_addr() == addr {
+            current.size += node.size;
+            current.next = node.next.take();
+            return;
+        }
+
+        let node_ptr = addr as *mut ListNode;
+        unsafe {
+            node_ptr.write(node);
+            current.next = Some(&mut *node_ptr);
+        }
+    }
+
+    /// Look for a free region with the given size and alignment and remove it from the list. Return
+    /// the region and the start address of the allocation within it.
+    fn find_region(&mut self, size: usize, align: usize) -> Option<(&'static mut ListNode, usize)> {
+        let mut current = &mut self.head;
+
+        while let Some(ref mut region) = current.next {
+            if let Some(alloc_start) = Self::alloc_from_region(region, size, align) {
+                // Unlink the region from the list
+                let next = region.next.take();
+                let found = current.next.take().map(|r| (r, alloc_start));
+                current.next = next;
+                return found;
+            }
+            current = current.next.as_mut().unwrap();
+        }
+
+        None
+    }
+
+    /// Try to use the passed region for an allocation with the given size and alignment. Return the
+    /// allocation start address on success.
+    fn alloc_from_region(region: &ListNode, size: usize, align: usize) -> Option<usize> {
+        // Any padding left in front of the allocation must be able to hold a `ListNode`
+        let mut alloc_start = align_up(region.start_addr(), align);
+        if alloc_start != region.start_addr()
+            && alloc_start - region.start_addr() < size_of::<ListNode>()
+        {
+            alloc_start = align_up(region.start_addr() + size_of::<ListNode>(), align);
+        }
+
+        // The region must be large enough, and any remainder must be able to hold a `ListNode`
+        let alloc_end = alloc_start.checked_add(size)?;
+        let excess_size = region.end_addr().checked_sub(alloc_end)?;
+        if excess_size > 0 && excess_size < size_of::<ListNode>() {
+            return None;
+        }
+
+        Some(alloc_start)
+    }
+
+    /// Return an iterator over the free regions
+    fn regions(&self) -> impl Iterator<Item = &ListNode> {
+        iter::successors(self.head.next.as_deref(), |r| r.next.as_deref())
+    }
+
+    /// Adjust the passed layout so that the resulting allocated memory region is also capable of
+    /// storing a `ListNode`. Return the adjusted size and alignment as a `(size, align)` tuple.
+    fn size_align(layout: Layout) -> (usize, usize) {
+        let layout = layout
+            .align_to(align_of::<ListNode>())
+            .expect("adjusting alignment failed")
+            .pad_to_align();
+        let size = layout.size().max(size_of::<ListNode>());
+        (size, layout.align())
+    }
+}
+
+impl HeapAllocator for LinkedListAllocator {
+    unsafe fn init(&mut self, heap_start: usize, heap_size: usize) {
+        unsafe { self.add_free_region(heap_start, heap_size) };
+        self.heap_end = heap_start + heap_size;
+    }
+
+    fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        // Use the first free region that fits
+        let (size, align) = Self::size_align(layout);
+        let Some((region, alloc_start)) = self.find_region(size, align) else {
+            return ptr::null_mut();
+        };
+
+        // Give the unused parts of the region back to the free list
+        let (region_start, region_end) = (region.start_addr(), region.end_addr());
+        let alloc_end = alloc_start + size;
+        unsafe {
+            if region_end > alloc_end {
+                self.add_free_region(alloc_end, region_end - alloc_end);
+            }
+            if alloc_start > region_start {
+                self.add_free_region(region_start, alloc_start - region_start);
+            }
+        }
+
+        alloc_start as *mut u8
+    }
+
+    unsafe fn deallocate(&mut self, ptr: *mut u8, layout: Layout) {
+        let (size, _) = Self::size_align(layout);
+        unsafe { self.add_free_region(ptr as usize, size) };
+    }
+
+    unsafe fn extend(&mut self, size: usize) {
+        // The new region is merged with the last free region if adjacent
+        unsafe { self.add_free_region(self.heap_end, size) };
+        self.heap_end += size;
+    }
+
+    fn free_bytes(&self) -> usize {
+        self.regions().map(|r| r.size).sum()
+    }
+
+    fn largest_free_block(&self) -> usize {
+        self.regions().map(|r| r.size).max().unwrap_or(0)
+    }
+}
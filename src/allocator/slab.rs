@@ -0,0 +1,323 @@
+//! Slab cache submodule
+
+use alloc::alloc::{alloc, dealloc};
+use core::alloc::Layout;
+use core::marker::PhantomData;
+use core::ptr::{self, NonNull};
+
+use super::{PAGE_SIZE, align_up};
+
+/// Minimum number of objects in each slab, for objects too large to fit a single page
+const MIN_OBJECTS_PER_SLAB: usize = 8;
+
+/// Maximum number of empty slabs a cache keeps around, to avoid thrashing on alloc/free cycles
+const MAX_EMPTY_SLABS: usize = 1;
+
+/// Slab header, stored at the start of each slab
+struct Slab {
+    prev: *mut Self,
+    next: *mut Self,
+    free: *mut FreeObject,
+    in_use: usize,
+}
+
+/// Free list node, stored in each free object slot
+struct FreeObject {
+    next: *mut Self,
+}
+
+/// Slab cache statistics
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SlabStats {
+    /// Size of each slab in bytes
+    pub slab_size: usize,
+    /// Number of objects that fit in each slab
+    pub objects_per_slab: usize,
+    /// Number of slabs currently owned by the cache
+    pub slabs: usize,
+    /// Number of objects currently allocated
+    pub objects_in_use: usize,
+    /// Number of free object slots in the owned slabs
+    pub objects_free: usize,
+    /// Number of allocations
+    pub allocations: usize,
+    /// Number of frees
+    pub frees: usize,
+}
+
+/// A cache of objects of type `T`, carved out of naturally aligned slabs taken from the heap.
+///
+/// Slabs are kept in three lists (partially used, full and empty), and objects are allocated from
+/// partially used slabs first, so that slabs can become empty and be given back to the heap.
+pub struct SlabCache<T> {
+    name: &'static str,
+    ctor: fn() -> T,
+    partial: *mut Slab,
+    full: *mut Slab,
+    empty: *mut Slab,
+    empty_slabs: usize,
+    slabs: usize,
+    in_use: usize,
+    allocations: usize,
+    frees: usize,
+    _marker: PhantomData<T>,
+}
+
+// The cache owns its slabs and the objects in them
+unsafe impl<T: Send> Send for SlabCache<T> {}
+
+impl<T> SlabCache<T> {
+    /// Alignment of each object slot
+    const SLOT_ALIGN: usize = max(align_of::<T>(), align_of::<FreeObject>());
+
+    /// Size of each object slot in bytes
+    const SLOT_SIZE: usize = align_up(
+        max(size_of::<T>(), size_of::<FreeObject>()),
+        Self::SLOT_ALIGN,
+    );
+
+    /// Offset of the first object slot from the start of a slab
+    const FIRST_SLOT: usize = align_up(size_of::<Slab>(), Self::SLOT_ALIGN);
+
+    /// Size of each slab in bytes, a power of two so that slabs can be aligned to their size
+    const SLAB_SIZE: usize = {
+        let min_size = Self::FIRST_SLOT + Self::SLOT_SIZE * MIN_OBJECTS_PER_SLAB;
+        if min_size <= PAGE_SIZE {
+            PAGE_SIZE
+        } else {
+            min_size.next_power_of_two()
+        }
+    };
+
+    /// Number of objects that fit in each slab
+    const OBJECTS_PER_SLAB: usize = (Self::SLAB_SIZE - Self::FIRST_SLOT) / Self::SLOT_SIZE;
+
+    /// Create a new empty [`SlabCache`] with the passed name, using `ctor` to construct the objects
+    /// returned by [`Self::alloc`]
+    #[must_use]
+    pub const fn new(name: &'static str, ctor: fn() -> T) -> Self {
+        Self {
+            name,
+            ctor,
+            partial: ptr::null_mut(),
+            full: ptr::null_mut(),
+            empty: ptr::null_mut(),
+            empty_slabs: 0,
+            slabs: 0,
+            in_use: 0,
+            allocations: 0,
+            frees: 0,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Return the name of the cache
+    #[must_use]
+    pub const fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Allocate an object initialized by the cache constructor. Return `None` if the heap is
+    /// exhausted.
+    pub fn alloc(&mut self) -> Option<NonNull<T>> {
+        self.alloc_with((self.ctor)())
+    }
+
+    /// Allocate an object initialized with the passed value. Return `None` if the heap is
+    /// exhausted.
+    pub fn alloc_with(&mut self, value: T) -> Option<NonNull<T>> {
+        // Prefer partially used slabs, then empty ones, and only then take a new slab from the heap
+        if self.partial.is_null() {
+            let slab = if self.empty.is_null() {
+                self.grow()?
+            } else {
+                let slab = self.empty;
+                unsafe { unlink(&mut self.empty, slab) };
+                self.empty_slabs -= 1;
+                slab
+            };
+            unsafe { push(&mut self.partial, slab) };
+        }
+
+        let slab = self.partial;
+        let obj = unsafe {
+            let obj = (*slab).free;
+            (*slab).free = (*obj).next;
+            (*slab).in_use += 1;
+            if (*slab).free.is_null() {
+                unlink(&mut self.partial, slab);
+                push(&mut self.full, slab);
+            }
+
+            let obj = obj.cast::<T>();
+            obj.write(value);
+            NonNull::new_unchecked(obj)
+        };
+
+        self.in_use += 1;
+        self.allocations += 1;
+        Some(obj)
+    }
+
+    /// Drop the passed object and give its slot back to the cache. A slab that becomes empty is
+    /// given back to the heap, unless the cache keeps it for later allocations.
+    ///
+    /// ## Safety
+    ///
+    /// The caller must guarantee that `obj` was allocated by this cache, and that it is not used
+    /// after this call.
+    pub unsafe fn free(&mut self, obj: NonNull<T>) {
+        let obj = obj.as_ptr();
+        unsafe {
+            obj.drop_in_place();
+
+            // Slabs are aligned to their size, so the header is found by masking the object address
+            let slab = (obj as usize & !(Self::SLAB_SIZE - 1)) as *mut Slab;
+            if (*slab).free.is_null() {
+                unlink(&mut self.full, slab);
+                push(&mut self.partial, slab);
+            }
+
+            let node = obj.cast::<FreeObject>();
+            node.write(FreeObject { next: (*slab).free });
+            (*slab).free = node;
+            (*slab).in_use -= 1;
+
+            if (*slab).in_use == 0 {
+                unlink(&mut self.partial, slab);
+                if self.empty_slabs < MAX_EMPTY_SLABS {
+                    push(&mut self.empty, slab);
+                    self.empty_slabs += 1;
+                } else {
+                    self.release(slab);
+                }
+            }
+        }
+
+        self.in_use -= 1;
+        self.frees += 1;
+    }
+
+    /// Give all empty slabs back to the heap, returning their number
+    pub fn shrink(&mut self) -> usize {
+        let released = self.empty_slabs;
+        while !self.empty.is_null() {
+            let slab = self.empty;
+            unsafe {
+                unlink(&mut self.empty, slab);
+                self.release(slab);
+            }
+        }
+
+        self.empty_slabs = 0;
+        released
+    }
+
+    /// Return the current [`SlabStats`] of the cache
+    #[must_use]
+    pub const fn stats(&self) -> SlabStats {
+        SlabStats {
+            slab_size: Self::SLAB_SIZE,
+            objects_per_slab: Self::OBJECTS_PER_SLAB,
+            slabs: self.slabs,
+            objects_in_use: self.in_use,
+            objects_free: self.slabs * Self::OBJECTS_PER_SLAB - self.in_use,
+            allocations: self.allocations,
+            frees: self.frees,
+        }
+    }
+
+    /// Return the layout of a slab
+    const fn slab_layout() -> Layout {
+        // The slab size is a power of two, so it's always a valid alignment
+        unsafe { Layout::from_size_align_unchecked(Self::SLAB_SIZE, Self::SLAB_SIZE) }
+    }
+
+    /// Take a new slab from the heap and thread all its object slots onto its free list. Return
+    /// `None` if the heap is exhausted.
+    #[allow(clippy::cast_ptr_alignment)] // Slabs are aligned to their size
+    fn grow(&mut self) -> Option<*mut Slab> {
+        let slab = unsafe { alloc(Self::slab_layout()) }.cast::<Slab>();
+        if slab.is_null() {
+            return None;
+        }
+
+        let first_slot = slab as usize + Self::FIRST_SLOT;
+        let mut free = ptr::null_mut();
+        for i in (0..Self::OBJECTS_PER_SLAB).rev() {
+            let node = (first_slot + i * Self::SLOT_SIZE) as *mut FreeObject;
+            unsafe { node.write(FreeObject { next: free }) };
+            free = node;
+        }
+
+        unsafe {
+            slab.write(Slab {
+                prev: ptr::null_mut(),
+                next: ptr::null_mut(),
+                free,
+                in_use: 0,
+            });
+        }
+
+        self.slabs += 1;
+        Some(slab)
+    }
+
+    /// Give the passed slab back to the heap.
+    ///
+    /// ## Safety
+    ///
+    /// The caller must guarantee that the slab is empty and not linked in any list.
+    unsafe fn release(&mut self, slab: *mut Slab) {
+        unsafe { dealloc(slab.cast(), Self::slab_layout()) };
+        self.slabs -= 1;
+    }
+}
+
+impl<T> Drop for SlabCache<T> {
+    /// Give the empty slabs back to the heap (slabs that still hold objects are leaked, since the
+    /// objects may still be in use)
+    fn drop(&mut self) {
+        self.shrink();
+    }
+}
+
+/// Return the larger of the two passed values
+const fn max(a: usize, b: usize) -> usize {
+    if a > b { a } else { b }
+}
+
+/// Push a slab onto the front of the passed list.
+///
+/// ## Safety
+///
+/// The caller must guarantee that the slab is valid and not linked in any list.
+unsafe fn push(list: &mut *mut Slab, slab: *mut Slab) {
+    unsafe {
+        (*slab).prev = ptr::null_mut();
+        (*slab).next = *list;
+        if !list.is_null() {
+            (**list).prev = slab;
+        }
+    }
+    *list = slab;
+}
+
+/// Unlink a slab from the passed list.
+///
+/// ## Safety
+///
+/// The caller must guarantee that the slab is linked in the passed list.
+unsafe fn unlink(list: &mut *mut Slab, slab: *mut Slab) {
+    unsafe {
+        let Slab { prev, next, .. } = *slab;
+        if prev.is_null() {
+            *list = next;
+        } else {
+            (*prev).next = next;
+        }
+        if !next.is_null() {
+            (*next).prev = prev;
+        }
+    }
+}
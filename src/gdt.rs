@@ -2,35 +2,33 @@
 
 use lazy_static::lazy_static;
 use x86_64::VirtAddr;
+use x86_64::instructions::interrupts;
 use x86_64::instructions::segmentation::{CS, Segment};
 use x86_64::instructions::tables::load_tss;
 use x86_64::structures::gdt::{Descriptor, GlobalDescriptorTable, SegmentSelector};
 use x86_64::structures::tss::TaskStateSegment;
 
+use crate::memory::stack::{self, StackError};
+use crate::serial_println;
+
 /// Index in the interrupt stack table for the double fault handler
 pub const DOUBLE_FAULT_IST_INDEX: u16 = 0;
 
-lazy_static! {
-    // TSS
-    static ref TSS: TaskStateSegment = {
-        // Create the TSS
-        let mut tss = TaskStateSegment::new();
+/// Number of entries in the interrupt stack table
+const IST_ENTRIES: usize = 7;
 
-        // TODO: Use a proper stack allocator and add a stack guard
-        tss.interrupt_stack_table[DOUBLE_FAULT_IST_INDEX as usize] = {
-            const STACK_SIZE: usize = 4096 * 5;
-            // Use a static mut array as stack storage
-            static mut STACK: [u8; STACK_SIZE] = [0; STACK_SIZE];
+/// Number of pages of each guarded interrupt stack
+const INTERRUPT_STACK_PAGES: u64 = 5;
 
-            let stack_start = VirtAddr::from_ptr(&raw const STACK);
+/// Size of the bootstrap double fault stack in bytes
+const BOOTSTRAP_STACK_SIZE: usize = 4096 * 5;
 
-            // Return the top of the stack
-            stack_start + STACK_SIZE.try_into().unwrap()
-        };
+/// Double fault stack used until [`init_interrupt_stacks`] is called, since the kernel memory is not
+/// available yet when the GDT is loaded (it has no guard page)
+static mut BOOTSTRAP_STACK: [u8; BOOTSTRAP_STACK_SIZE] = [0; BOOTSTRAP_STACK_SIZE];
 
-        tss
-    };
-}
+/// TSS, whose interrupt stack table is updated in place when the interrupt stacks are installed
+static mut TSS: TaskStateSegment = TaskStateSegment::new();
 
 struct Selectors {
     cs_sel: SegmentSelector,
@@ -43,11 +41,11 @@ lazy_static! {
         // Create the GDT and the selectors
         let mut gdt = GlobalDescriptorTable::new();
         let cs_sel = gdt.append(Descriptor::kernel_code_segment());
-        let tss_sel = gdt.append(Descriptor::tss_segment(&TSS));
+        let tss_sel = gdt.append(unsafe { Descriptor::tss_segment_unchecked(&raw const TSS) });
 
         // Add the kernel code and TSS segments
         gdt.append(Descriptor::kernel_code_segment());
-        gdt.append(Descriptor::tss_segment(&TSS));
+        gdt.append(unsafe { Descriptor::tss_segment_unchecked(&raw const TSS) });
 
         (gdt, Selectors { cs_sel, tss_sel })
     };
@@ -55,6 +53,10 @@ lazy_static! {
 
 /// Load GDT, CS, and TSS in the CPU
 pub fn init() {
+    // Use the bootstrap stack for double faults until the guarded interrupt stacks are installed
+    let stack_top = VirtAddr::from_ptr(&raw const BOOTSTRAP_STACK) + BOOTSTRAP_STACK_SIZE as u64;
+    unsafe { set_interrupt_stack(DOUBLE_FAULT_IST_INDEX.into(), stack_top) };
+
     GDT.0.load();
 
     unsafe {
@@ -62,3 +64,57 @@ pub fn init() {
         load_tss(GDT.1.tss_sel);
     }
 }
+
+/// Allocate a guarded stack for each entry in the interrupt stack table and install it in the TSS,
+/// replacing the bootstrap double fault stack.
+///
+/// Requires the kernel memory to be initialized with [`memory::init_kernel_memory`].
+///
+/// ## Errors
+///
+/// Returns a [`StackError`] if a stack can't be allocated, in which case the stacks allocated so far
+/// are freed and the TSS is left unchanged.
+///
+/// [`memory::init_kernel_memory`]: crate::memory::init_kernel_memory
+pub fn init_interrupt_stacks() -> Result<(), StackError> {
+    let mut stacks = [None; IST_ENTRIES];
+    for index in 0..IST_ENTRIES {
+        match stack::alloc_stack(INTERRUPT_STACK_PAGES) {
+            Ok(stack) => stacks[index] = Some(stack),
+            Err(err) => {
+                // Free the stacks in reverse order, so that their room in the region is reclaimed,
+                // and report the allocation error even if some of them can't be freed
+                for stack in stacks[..index].iter().rev().flatten() {
+                    if let Err(free_err) = unsafe { stack::free_stack(*stack) } {
+                        serial_println!(
+                            "failed to free interrupt stack {:?}: {:?}",
+                            stack,
+                            free_err
+                        );
+                    }
+                }
+                return Err(err);
+            }
+        }
+    }
+
+    // The CPU reads the interrupt stack table on each interrupt, so the TSS doesn't need reloading
+    interrupts::without_interrupts(|| {
+        for (index, stack) in stacks.into_iter().flatten().enumerate() {
+            unsafe { set_interrupt_stack(index, stack.end()) };
+        }
+    });
+
+    Ok(())
+}
+
+/// Set the stack top of the passed entry in the interrupt stack table.
+///
+/// ## Safety
+///
+/// The caller must guarantee that the stack is valid and not used by anything else, and that no
+/// interrupt using the entry can occur while it's being updated.
+unsafe fn set_interrupt_stack(index: usize, stack_top: VirtAddr) {
+    let tss = &raw mut TSS;
+    unsafe { (*tss).interrupt_stack_table[index] = stack_top };
+}
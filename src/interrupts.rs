@@ -8,7 +8,11 @@ use x86_64::instructions::port::Port;
 use x86_64::registers::control::Cr2;
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
 
-use crate::{gdt, hlt_loop, print, println};
+use self::apic::{ApicError, ApicMode};
+use crate::memory::{region, user};
+use crate::{gdt, hlt_loop, print, println, time};
+
+pub mod apic;
 
 /// PIC1 interrupt offset
 const PIC1_OFFSET: u8 = 32;
@@ -24,6 +28,68 @@ pub fn init_pics() {
     unsafe { PICS.lock().initialize() };
 }
 
+/// Switch external interrupts from the chained PICs to the APICs, masking the PICs off.
+///
+/// The APICs are needed for SMP, MSI and the local APIC timer. The ISA IRQs of the timer, the
+/// keyboard and the RTC keep their interrupt vectors.
+///
+/// Since the APIC registers are mapped with [`map_mmio`], this must be called after the kernel
+/// memory has been initialized. The kernel keeps using the PICs if this fails.
+///
+/// ## Errors
+///
+/// Returns an [`ApicError`] if the CPU has no local APIC, if the APICs have already been
+/// initialized, if the ACPI MADT doesn't report the I/O APIC, or if mapping their registers fails.
+///
+/// [`map_mmio`]: crate::memory::mmio::map_mmio
+pub fn init_apic() -> Result<ApicMode, ApicError> {
+    let isa_irqs = [
+        InterruptIndex::Timer,
+        InterruptIndex::Keyboard,
+        InterruptIndex::Rtc,
+    ]
+    .map(|i| (i.isa_irq(), i.as_u8()));
+
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mode = apic::init(&isa_irqs)?;
+        unsafe { PICS.lock().disable() };
+        Ok(mode)
+    })
+}
+
+/// Unmask the passed interrupt on the chained PICs, with the cascade IRQ for the secondary PIC.
+///
+/// This is a no-op once the APICs are enabled, since [`init_apic`] routes all the interrupts of
+/// [`InterruptIndex`].
+pub fn unmask_pic_irq(index: InterruptIndex) {
+    /// ISA IRQ of the secondary PIC on the primary PIC
+    const CASCADE_IRQ: u8 = 2;
+
+    if apic::is_enabled() {
+        return;
+    }
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut pics = PICS.lock();
+        let [mut mask1, mut mask2] = unsafe { pics.read_masks() };
+        match index.isa_irq() {
+            irq @ 0..8 => mask1 &= !(1 << irq),
+            irq => {
+                mask1 &= !(1 << CASCADE_IRQ);
+                mask2 &= !(1 << (irq - 8));
+            }
+        }
+        unsafe { pics.write_masks(mask1, mask2) };
+    });
+}
+
+/// Signal the end of the passed interrupt to the active interrupt controller
+fn end_of_interrupt(index: InterruptIndex) {
+    match apic::local_apic() {
+        Some(local_apic) => local_apic.end_of_interrupt(),
+        None => unsafe { PICS.lock().notify_end_of_interrupt(index.as_u8()) },
+    }
+}
+
 /// Interrupt variants
 #[derive(Debug, Clone, Copy)]
 #[repr(u8)]
@@ -32,6 +98,8 @@ pub enum InterruptIndex {
     Timer = PIC1_OFFSET,
     /// Keyboard interrupt
     Keyboard,
+    /// Real-time clock interrupt
+    Rtc = PIC2_OFFSET,
 }
 
 impl InterruptIndex {
@@ -39,6 +107,11 @@ impl InterruptIndex {
     const fn as_u8(self) -> u8 {
         self as u8
     }
+
+    /// Return the ISA IRQ of the interrupt
+    const fn isa_irq(self) -> u8 {
+        self.as_u8() - PIC1_OFFSET
+    }
 }
 
 lazy_static! {
@@ -57,6 +130,8 @@ lazy_static! {
         idt.breakpoint.set_handler_fn(breakpoint_handler);
         idt[InterruptIndex::Timer.as_u8()].set_handler_fn(timer_interrupt_handler);
         idt[InterruptIndex::Keyboard.as_u8()].set_handler_fn(keyboard_interrupt_handler);
+        idt[InterruptIndex::Rtc.as_u8()].set_handler_fn(rtc_interrupt_handler);
+        idt[apic::SPURIOUS_VECTOR].set_handler_fn(spurious_interrupt_handler);
 
         idt
     };
@@ -77,11 +152,31 @@ extern "x86-interrupt" fn double_fault_handler(
 
 // Page fault handler
 extern "x86-interrupt" fn page_fault_handler(
-    stack_frame: InterruptStackFrame,
+    mut stack_frame: InterruptStackFrame,
     error_code: PageFaultErrorCode,
 ) {
+    // Map pages of demand-paged regions on first access, and retry the access
+    let addr = Cr2::read();
+    if let Ok(addr) = addr
+        && region::handle_page_fault(addr, error_code)
+    {
+        return;
+    }
+
+    // Resume faulting user memory accesses of the kernel at their fixup code, which reports the error
+    if !error_code.contains(PageFaultErrorCode::USER_MODE)
+        && let Some(fixup) = user::fixup_address(stack_frame.instruction_pointer)
+    {
+        unsafe {
+            stack_frame
+                .as_mut()
+                .update(|frame| frame.instruction_pointer = fixup);
+        }
+        return;
+    }
+
     println!("EXCEPTION: PAGE FAULT");
-    println!("Accessed Address: {:?}", Cr2::read());
+    println!("Accessed Address: {addr:?}");
     println!("Error Code: {error_code:?}");
     println!("{stack_frame:#?}");
     hlt_loop();
@@ -94,13 +189,10 @@ extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
 
 /// Timer interrupt handler
 extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    print!(".");
+    time::tick();
 
     // Notify the end of interrupt
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
-    }
+    end_of_interrupt(InterruptIndex::Timer);
 }
 
 /// Keyboard interrupt handler
@@ -134,12 +226,21 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
     }
 
     // Notify the end of interrupt
-    unsafe {
-        PICS.lock()
-            .notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8());
-    }
+    end_of_interrupt(InterruptIndex::Keyboard);
 }
 
+/// Real-time clock interrupt handler
+extern "x86-interrupt" fn rtc_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    time::rtc::handle_interrupt();
+
+    // Notify the end of interrupt
+    end_of_interrupt(InterruptIndex::Rtc);
+}
+
+/// Spurious local APIC interrupt handler, which must not signal the end of interrupt
+#[allow(clippy::missing_const_for_fn)] // Interrupt handlers are not called from Rust code
+extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {}
+
 #[cfg(test)]
 mod tests {
     use x86_64::instructions::interrupts::int3;
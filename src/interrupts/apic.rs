@@ -0,0 +1,374 @@
+//! Advanced Programmable Interrupt Controller submodule
+//!
+//! The local APIC is driven through its MSRs in x2APIC mode when the CPU supports it, and through
+//! its memory-mapped registers otherwise. ISA IRQs are routed to the local APIC of the bootstrap
+//! processor by the I/O APIC, which is found through the ACPI MADT along with the interrupt source
+//! overrides of the ISA IRQs.
+
+use core::arch::x86_64::__cpuid;
+
+use spin::{Mutex, Once};
+use x86_64::PhysAddr;
+use x86_64::instructions::interrupts;
+use x86_64::registers::model_specific::Msr;
+
+use crate::acpi::{self, AcpiError, InterruptOverride};
+use crate::memory::mmio::{self, Mmio, MmioError};
+
+/// Interrupt vector of spurious local APIC interrupts
+pub const SPURIOUS_VECTOR: u8 = 0xff;
+
+/// CPUID leaf that reports the processor features
+const CPUID_FEATURES: u32 = 1;
+/// Bit in `EDX` of the features leaf that reports local APIC support
+const CPUID_APIC: u32 = 1 << 9;
+/// Bit in `ECX` of the features leaf that reports x2APIC support
+const CPUID_X2APIC: u32 = 1 << 21;
+
+/// Model-specific register that holds the local APIC base address and mode
+const IA32_APIC_BASE: u32 = 0x1b;
+/// Global enable bit of [`IA32_APIC_BASE`]
+const APIC_BASE_ENABLE: u64 = 1 << 11;
+/// x2APIC mode bit of [`IA32_APIC_BASE`]
+const APIC_BASE_X2APIC: u64 = 1 << 10;
+/// Mask of the base address in [`IA32_APIC_BASE`]
+const APIC_BASE_ADDR_MASK: u64 = 0x000f_ffff_ffff_f000;
+/// First model-specific register of the local APIC in x2APIC mode
+const X2APIC_MSR_BASE: u32 = 0x800;
+
+/// Local APIC ID register
+const LAPIC_ID: u32 = 0x20;
+/// Local APIC version register
+const LAPIC_VERSION: u32 = 0x30;
+/// Local APIC task priority register
+const LAPIC_TPR: u32 = 0x80;
+/// Local APIC end of interrupt register
+const LAPIC_EOI: u32 = 0xb0;
+/// Local APIC spurious interrupt vector register
+const LAPIC_SVR: u32 = 0xf0;
+/// Software enable bit of [`LAPIC_SVR`]
+const LAPIC_SVR_ENABLE: u32 = 1 << 8;
+
+/// I/O APIC version register
+const IO_APIC_VERSION: u32 = 0x01;
+/// First I/O APIC redirection table register
+const IO_APIC_REDIRECTION_TABLE: u32 = 0x10;
+/// Offset of the I/O APIC register select register
+const IO_APIC_REGSEL: usize = 0x00;
+/// Offset of the I/O APIC data register
+const IO_APIC_WINDOW: usize = 0x10;
+/// Active low polarity bit of an I/O APIC redirection entry
+const REDIRECTION_ACTIVE_LOW: u64 = 1 << 13;
+/// Level-triggered mode bit of an I/O APIC redirection entry
+const REDIRECTION_LEVEL: u64 = 1 << 15;
+/// Mask bit of an I/O APIC redirection entry
+const REDIRECTION_MASKED: u64 = 1 << 16;
+
+/// Local APIC of the bootstrap processor, once [`init`] has been called
+static LOCAL_APIC: Once<LocalApic> = Once::new();
+
+/// I/O APIC, once [`init`] has been called
+static IO_APIC: Mutex<Option<IoApic>> = Mutex::new(None);
+
+/// Errors that can occur when initializing the APICs
+#[derive(Debug)]
+pub enum ApicError {
+    /// The CPU has no local APIC
+    Unsupported,
+    /// The APICs have already been initialized
+    AlreadyInitialized,
+    /// The ID of the local APIC doesn't fit in an I/O APIC redirection entry
+    InvalidApicId(u32),
+    /// The I/O APIC couldn't be found in the ACPI tables
+    Acpi(AcpiError),
+    /// Mapping the APIC registers failed
+    Mmio(MmioError),
+}
+
+impl From<AcpiError> for ApicError {
+    fn from(err: AcpiError) -> Self {
+        Self::Acpi(err)
+    }
+}
+
+impl From<MmioError> for ApicError {
+    fn from(err: MmioError) -> Self {
+        Self::Mmio(err)
+    }
+}
+
+/// Access mode of the local APIC
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApicMode {
+    /// Memory-mapped registers
+    XApic,
+    /// Model-specific registers
+    X2Apic,
+}
+
+/// The local APIC of the current processor
+#[derive(Debug)]
+pub enum LocalApic {
+    /// Local APIC with memory-mapped registers
+    XApic(Mmio),
+    /// Local APIC in x2APIC mode
+    X2Apic,
+}
+
+impl LocalApic {
+    /// Return the access mode of the local APIC
+    #[must_use]
+    pub const fn mode(&self) -> ApicMode {
+        match self {
+            Self::XApic(_) => ApicMode::XApic,
+            Self::X2Apic => ApicMode::X2Apic,
+        }
+    }
+
+    /// Return the ID of the local APIC
+    #[must_use]
+    pub fn id(&self) -> u32 {
+        match self {
+            Self::XApic(_) => self.read(LAPIC_ID) >> 24,
+            Self::X2Apic => self.read(LAPIC_ID),
+        }
+    }
+
+    /// Return the version of the local APIC
+    #[must_use]
+    pub fn version(&self) -> u8 {
+        #[allow(clippy::cast_possible_truncation)] // The version is in the low byte
+        let version = self.read(LAPIC_VERSION) as u8;
+        version
+    }
+
+    /// Signal the end of the interrupt being serviced
+    pub fn end_of_interrupt(&self) {
+        self.write(LAPIC_EOI, 0);
+    }
+
+    /// Read the local APIC register at the passed offset
+    fn read(&self, offset: u32) -> u32 {
+        match self {
+            Self::XApic(mmio) => mmio.read(offset as usize),
+            #[allow(clippy::cast_possible_truncation)] // Registers are 32-bit wide, except ICR
+            Self::X2Apic => unsafe { Msr::new(X2APIC_MSR_BASE + (offset >> 4)).read() as u32 },
+        }
+    }
+
+    /// Write the local APIC register at the passed offset
+    fn write(&self, offset: u32, value: u32) {
+        match self {
+            Self::XApic(mmio) => mmio.write(offset as usize, value),
+            Self::X2Apic => unsafe {
+                Msr::new(X2APIC_MSR_BASE + (offset >> 4)).write(u64::from(value));
+            },
+        }
+    }
+}
+
+/// An I/O APIC, which routes global system interrupts to local APICs
+#[derive(Debug)]
+pub struct IoApic {
+    mmio: Mmio,
+}
+
+impl IoApic {
+    /// Return the number of redirection entries, i.e. of global system interrupts handled
+    #[must_use]
+    pub fn entries(&self) -> u32 {
+        ((self.read(IO_APIC_VERSION) >> 16) & 0xff) + 1
+    }
+
+    /// Return the redirection entry of the passed global system interrupt
+    #[must_use]
+    pub fn redirection(&self, gsi: u32) -> u64 {
+        let reg = IO_APIC_REDIRECTION_TABLE + gsi * 2;
+        u64::from(self.read(reg)) | u64::from(self.read(reg + 1)) << 32
+    }
+
+    /// Route the passed global system interrupt to the passed vector of the local APIC with the
+    /// passed ID, as a fixed, edge-triggered, active-high interrupt
+    pub fn route(&self, gsi: u32, vector: u8, apic_id: u8) {
+        self.set_redirection(gsi, u64::from(vector) | u64::from(apic_id) << 56);
+    }
+
+    /// Route the global system interrupt of the passed interrupt source override to the passed
+    /// vector of the local APIC with the passed ID, with the polarity and trigger mode it reports
+    pub fn route_override(&self, source: &InterruptOverride, vector: u8, apic_id: u8) {
+        let mut entry = u64::from(vector) | u64::from(apic_id) << 56;
+        if source.active_low {
+            entry |= REDIRECTION_ACTIVE_LOW;
+        }
+        if source.level_triggered {
+            entry |= REDIRECTION_LEVEL;
+        }
+        self.set_redirection(source.gsi, entry);
+    }
+
+    /// Mask the passed global system interrupt
+    pub fn mask(&self, gsi: u32) {
+        self.set_redirection(gsi, self.redirection(gsi) | REDIRECTION_MASKED);
+    }
+
+    /// Set the redirection entry of the passed global system interrupt
+    fn set_redirection(&self, gsi: u32, entry: u64) {
+        let reg = IO_APIC_REDIRECTION_TABLE + gsi * 2;
+
+        // Mask the entry while it is half-written
+        #[allow(clippy::cast_possible_truncation)] // Split the entry in two halves
+        {
+            self.write(reg, (entry as u32) | REDIRECTION_MASKED as u32);
+            self.write(reg + 1, (entry >> 32) as u32);
+            self.write(reg, entry as u32);
+        }
+    }
+
+    /// Read the I/O APIC register with the passed index
+    fn read(&self, reg: u32) -> u32 {
+        self.mmio.write(IO_APIC_REGSEL, reg);
+        self.mmio.read(IO_APIC_WINDOW)
+    }
+
+    /// Write the I/O APIC register with the passed index
+    fn write(&self, reg: u32, value: u32) {
+        self.mmio.write(IO_APIC_REGSEL, reg);
+        self.mmio.write(IO_APIC_WINDOW, value);
+    }
+}
+
+/// Return `true` if the CPU has a local APIC
+#[must_use]
+pub fn supports_apic() -> bool {
+    __cpuid(CPUID_FEATURES).edx & CPUID_APIC != 0
+}
+
+/// Return `true` if the local APIC supports x2APIC mode
+#[must_use]
+pub fn supports_x2apic() -> bool {
+    __cpuid(CPUID_FEATURES).ecx & CPUID_X2APIC != 0
+}
+
+/// Return the global system interrupt that the passed ISA IRQ is connected to, according to the
+/// interrupt source overrides of the MADT (ISA IRQs without one are identity-mapped).
+///
+/// ## Errors
+///
+/// Returns an [`AcpiError`] if the MADT can't be read.
+pub fn isa_irq_to_gsi(irq: u8) -> Result<u32, AcpiError> {
+    let madt = acpi::madt()?;
+    Ok(madt
+        .isa_override(irq)
+        .map_or_else(|| u32::from(irq), |o| o.gsi))
+}
+
+/// Return the local APIC of the bootstrap processor, or `None` if [`init`] has not been called
+#[must_use]
+pub fn local_apic() -> Option<&'static LocalApic> {
+    LOCAL_APIC.get()
+}
+
+/// Return `true` if external interrupts are delivered through the APICs
+#[must_use]
+pub fn is_enabled() -> bool {
+    LOCAL_APIC.is_completed()
+}
+
+/// Run the passed closure with the I/O APIC, or return `None` if [`init`] has not been called
+pub fn with_io_apic<R>(f: impl FnOnce(&IoApic) -> R) -> Option<R> {
+    interrupts::without_interrupts(|| IO_APIC.lock().as_ref().map(f))
+}
+
+/// Enable the local APIC of the bootstrap processor (in x2APIC mode if available) and the I/O APIC,
+/// then route the passed ISA IRQs to the passed vectors. Interrupts are disabled in the meantime.
+///
+/// The caller is responsible for masking the 8259 PICs. On failure, the local APIC is left in the
+/// mode it was in.
+///
+/// ## Errors
+///
+/// Returns an [`ApicError`] if the CPU has no local APIC, if the APICs have already been
+/// initialized, if the MADT doesn't report the I/O APIC, or if mapping their registers fails.
+pub(super) fn init(isa_irqs: &[(u8, u8)]) -> Result<ApicMode, ApicError> {
+    if !supports_apic() {
+        return Err(ApicError::Unsupported);
+    }
+    if is_enabled() {
+        return Err(ApicError::AlreadyInitialized);
+    }
+    let madt = acpi::madt()?;
+
+    interrupts::without_interrupts(|| {
+        // Map the I/O APIC before touching the local APIC, which is enabled last
+        let io_apic = IoApic {
+            mmio: mmio::map_mmio(madt.io_apic.address, IO_APIC_WINDOW + 4)?,
+        };
+        let (local_apic, apic_id) = enable_local_apic()?;
+
+        // Mask all global system interrupts, except the passed ISA IRQs
+        for gsi in 0..io_apic.entries() {
+            io_apic.mask(gsi);
+        }
+        for &(irq, vector) in isa_irqs {
+            match madt.isa_override(irq) {
+                Some(source) => io_apic.route_override(&source, vector, apic_id),
+                None => io_apic.route(u32::from(irq), vector, apic_id),
+            }
+        }
+
+        let mode = local_apic.mode();
+        *IO_APIC.lock() = Some(io_apic);
+        LOCAL_APIC.call_once(|| local_apic);
+        Ok(mode)
+    })
+}
+
+/// Enable the local APIC of the current processor, accepting all interrupts, and return it with its
+/// ID. On failure, [`IA32_APIC_BASE`] is restored.
+fn enable_local_apic() -> Result<(LocalApic, u8), ApicError> {
+    let mut apic_base = Msr::new(IA32_APIC_BASE);
+    let base = unsafe { apic_base.read() };
+
+    let local_apic = if supports_x2apic() {
+        // The local APIC must be globally enabled before switching to x2APIC mode
+        unsafe {
+            apic_base.write(base | APIC_BASE_ENABLE);
+            apic_base.write(base | APIC_BASE_ENABLE | APIC_BASE_X2APIC);
+        }
+        LocalApic::X2Apic
+    } else {
+        // Map the registers first, so that a failure leaves the local APIC untouched
+        let phys = PhysAddr::new(base & APIC_BASE_ADDR_MASK);
+        let mmio = mmio::map_mmio(phys, 0x400)?;
+        unsafe { apic_base.write(base | APIC_BASE_ENABLE) };
+        LocalApic::XApic(mmio)
+    };
+
+    // I/O APIC redirection entries only hold 8-bit destination IDs
+    let id = local_apic.id();
+    let Ok(apic_id) = u8::try_from(id) else {
+        unsafe { restore_apic_base(base) };
+        return Err(ApicError::InvalidApicId(id));
+    };
+
+    local_apic.write(LAPIC_TPR, 0);
+    local_apic.write(LAPIC_SVR, LAPIC_SVR_ENABLE | u32::from(SPURIOUS_VECTOR));
+    Ok((local_apic, apic_id))
+}
+
+/// Restore the passed value of [`IA32_APIC_BASE`], read before enabling the local APIC.
+///
+/// ## Safety
+///
+/// The local APIC must not be in use.
+unsafe fn restore_apic_base(base: u64) {
+    let mut apic_base = Msr::new(IA32_APIC_BASE);
+    unsafe {
+        // x2APIC mode can only be left by disabling the local APIC, which resets it
+        let current = apic_base.read();
+        if current & APIC_BASE_X2APIC != 0 && base & APIC_BASE_X2APIC == 0 {
+            apic_base.write(current & !(APIC_BASE_ENABLE | APIC_BASE_X2APIC));
+        }
+        apic_base.write(base);
+    }
+}
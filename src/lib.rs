@@ -21,6 +21,7 @@
 #![cfg_attr(test, no_main)]
 #![feature(custom_test_frameworks)]
 #![feature(abi_x86_interrupt)]
+#![feature(alloc_error_handler)]
 #![feature(let_chains)]
 #![test_runner(crate::test_runner)]
 #![reexport_test_harness_main = "test_main"]
@@ -29,14 +30,20 @@ extern crate alloc;
 
 use core::panic::PanicInfo;
 
+use bootloader::BootInfo;
 #[cfg(test)]
-use bootloader::{BootInfo, entry_point};
+use bootloader::entry_point;
+use x86_64::VirtAddr;
 
+use crate::memory::buddy::BuddyFrameAllocator;
+
+pub mod acpi;
 pub mod allocator;
 pub mod gdt;
 pub mod interrupts;
 pub mod memory;
 pub mod serial;
+pub mod time;
 pub mod vga_buffer;
 
 /// Something that can be tested
@@ -66,6 +73,23 @@ pub fn test_runner(tests: &[&dyn Testable]) {
     exit_qemu(QemuExitCode::Success);
 }
 
+/// Initialize the OS for integration tests, along with the physical memory manager, the heap and
+/// the kernel memory.
+///
+/// ## Panics
+///
+/// Panics if the frame allocator or the heap can't be initialized.
+pub fn test_init(boot_info: &'static BootInfo) {
+    init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) }
+            .expect("frame allocator initialization failed");
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::init_kernel_memory(mapper, frame_allocator);
+}
+
 /// Panic handler helper for tests
 pub fn test_panic_handler(info: &PanicInfo) -> ! {
     serial_println!("[failed]\n");
@@ -109,7 +133,11 @@ pub fn init() {
     // Load the IDT
     interrupts::init_idt();
 
-    // Enable external interrupts
+    // Enable no-execute pages, write protection, SMEP and SMAP
+    memory::protect::init();
+
+    // Enable external interrupts, with the timer interrupt at the default frequency
+    time::init();
     interrupts::init_pics();
     x86_64::instructions::interrupts::enable();
 }
@@ -15,7 +15,8 @@ use alloc::vec::Vec;
 use core::panic::PanicInfo;
 
 use bootloader::{BootInfo, entry_point};
-use rust_os::{allocator, memory};
+use rust_os::memory::buddy::BuddyFrameAllocator;
+use rust_os::{allocator, gdt, interrupts, memory, time};
 use rust_os::{hlt_loop, println};
 use x86_64::VirtAddr;
 
@@ -49,10 +50,26 @@ fn kernel_main(boot_info: &'static BootInfo) -> ! {
     let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
     let mut mapper = unsafe { memory::init(phys_mem_offset) };
     let mut frame_allocator =
-        unsafe { memory::BootInfoFrameAllocator::init(&boot_info.memory_map) };
+        unsafe { BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) }
+            .expect("frame allocator initialization failed");
 
-    // Initialize the heap
+    // Initialize the heap and let it grow on demand
     allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::init_kernel_memory(mapper, frame_allocator);
+
+    // Make the writable mappings left by the bootloader non-executable
+    memory::protect::enforce_wx().expect("W^X enforcement failed");
+
+    // Move the interrupt stacks to guarded stacks
+    gdt::init_interrupt_stacks().expect("interrupt stack allocation failed");
+
+    // Deliver external interrupts through the APICs if the machine has them
+    match interrupts::init_apic() {
+        Ok(mode) => println!("APIC enabled ({mode:?})"),
+        Err(err) => println!("APIC unavailable ({err:?}), using the 8259 PICs"),
+    }
+    println!("Clock source: {}", time::init_clock());
+    println!("Date: {}", time::rtc::read());
 
     // Allocate a number on the heap
     let heap_value = Box::new(41);
@@ -1,10 +1,44 @@
 //! Memory module
 
+use core::ops::Range;
+use core::sync::atomic::{AtomicU64, Ordering};
+
 use bootloader::bootinfo::{MemoryMap, MemoryRegionType};
+use spin::Mutex;
 use x86_64::registers::control::Cr3;
-use x86_64::structures::paging::{FrameAllocator, OffsetPageTable, PageTable, PhysFrame, Size4KiB};
+use x86_64::structures::paging::frame::PhysFrameRange;
+use x86_64::structures::paging::page_table::PageTableLevel;
+use x86_64::structures::paging::{
+    FrameAllocator, OffsetPageTable, PageTable, PageTableFlags, PhysFrame, Size4KiB,
+};
 use x86_64::{PhysAddr, VirtAddr};
 
+use self::buddy::BuddyFrameAllocator;
+
+pub mod bitmap;
+pub mod buddy;
+pub mod huge;
+pub mod mmio;
+pub mod protect;
+pub mod region;
+pub mod stack;
+pub mod user;
+pub mod walk;
+
+/// Page table mapper and frame allocator that the kernel uses to change its mappings at runtime
+pub struct KernelMemory {
+    /// Mapper for the active level 4 page table
+    pub mapper: OffsetPageTable<'static>,
+    /// Physical frame allocator
+    pub frame_allocator: BuddyFrameAllocator,
+}
+
+/// Kernel memory, available after [`init_kernel_memory`] has been called
+static KERNEL_MEMORY: Mutex<Option<KernelMemory>> = Mutex::new(None);
+
+/// Virtual address where the physical memory is mapped, recorded by [`init`] (0 until then)
+static PHYSICAL_MEMORY_OFFSET: AtomicU64 = AtomicU64::new(0);
+
 /// A [`FrameAllocator`] that always returns `None`
 pub struct EmptyFrameAllocator;
 
@@ -18,10 +52,87 @@ unsafe impl FrameAllocator<Size4KiB> for EmptyFrameAllocator {
     }
 }
 
+/// Size of a 4 KiB page frame in bytes
+const PAGE_SIZE: u64 = 4096;
+
+/// Maximum number of usable regions tracked by the physical memory managers (the bootloader's
+/// memory map holds at most 64 regions)
+const MAX_USABLE_REGIONS: usize = 64;
+
+/// Errors that can occur when frames are given back to a physical memory manager
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameError {
+    /// The frame is already free
+    DoubleFree(PhysFrame),
+    /// The frame is outside the memory managed by the allocator
+    OutOfRange(PhysFrame),
+    /// The frame is not aligned to the size of the block it is supposed to start
+    Misaligned(PhysFrame),
+}
+
+/// Return an iterator over the address ranges of the usable regions in the memory map
+fn usable_regions(memory_map: &MemoryMap) -> impl Iterator<Item = Range<u64>> + '_ {
+    memory_map
+        .iter()
+        .filter(|r| r.region_type == MemoryRegionType::Usable)
+        .map(|r| r.range.start_addr()..r.range.end_addr())
+}
+
+/// The physical memory handed to a physical memory manager: the usable regions of the memory map,
+/// minus the area that holds the manager's own metadata
+struct ManagedMemory {
+    regions: [Range<u64>; MAX_USABLE_REGIONS],
+    len: usize,
+    metadata: Range<u64>,
+}
+
+impl ManagedMemory {
+    /// Record the usable regions of the passed memory map, excluding the passed metadata area
+    fn new(memory_map: &MemoryMap, metadata: Range<u64>) -> Self {
+        let mut managed = Self {
+            regions: [const { 0..0 }; MAX_USABLE_REGIONS],
+            len: 0,
+            metadata,
+        };
+        for (slot, region) in managed.regions.iter_mut().zip(usable_regions(memory_map)) {
+            *slot = region;
+            managed.len += 1;
+        }
+        managed
+    }
+
+    /// Return `true` if the passed physical address range lies in a single usable region and
+    /// doesn't overlap the metadata area
+    fn contains(&self, range: &Range<u64>) -> bool {
+        let overlaps_metadata = range.start < self.metadata.end && self.metadata.start < range.end;
+        !overlaps_metadata
+            && self.regions[..self.len]
+                .iter()
+                .any(|r| r.start <= range.start && range.end <= r.end)
+    }
+}
+
+/// Return the range of frames that contain the passed physical address range
+fn frame_range(range: Range<u64>) -> PhysFrameRange {
+    PhysFrame::range(
+        PhysFrame::containing_address(PhysAddr::new(range.start)),
+        PhysFrame::containing_address(PhysAddr::new(range.end).align_up(PAGE_SIZE)),
+    )
+}
+
+/// Return the physical address of the first usable region that can hold `size` bytes, used to store
+/// the metadata of the physical memory managers.
+fn find_usable_area(memory_map: &MemoryMap, size: u64) -> Option<PhysAddr> {
+    usable_regions(memory_map)
+        .find(|r| r.end - r.start >= size)
+        .map(|r| PhysAddr::new(r.start))
+}
+
 /// A [`FrameAllocator`] that returns usable frames from the bootloader's memory map
 pub struct BootInfoFrameAllocator {
     memory_map: &'static MemoryMap,
-    next: usize,
+    region: usize,
+    next: u64,
 }
 
 impl BootInfoFrameAllocator {
@@ -35,35 +146,32 @@ impl BootInfoFrameAllocator {
     pub const unsafe fn init(memory_map: &'static MemoryMap) -> Self {
         Self {
             memory_map,
+            region: 0,
             next: 0,
         }
     }
-
-    /// Return an iterator over the usable frames specified in the memory map
-    fn usable_frames(&self) -> impl Iterator<Item = PhysFrame> {
-        const PAGE_SIZE: usize = 4096;
-
-        // Get usable regions from the memory map
-        let regions = self.memory_map.iter();
-        let usable_regions = regions.filter(|r| r.region_type == MemoryRegionType::Usable);
-
-        // Map each region to its address range
-        let addr_ranges = usable_regions.map(|r| r.range.start_addr()..r.range.end_addr());
-
-        // Transform to an iterator of frame start addresses (the bootloader already page-aligns all
-        // usable memory regions, so no alignment code is needed here)
-        let frame_addresses = addr_ranges.flat_map(|r| r.step_by(PAGE_SIZE));
-
-        // Convert each start address to `PhysFrame` to construct the iterator
-        frame_addresses.map(|addr| PhysFrame::containing_address(PhysAddr::new(addr)))
-    }
 }
 
 unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
     fn allocate_frame(&mut self) -> Option<PhysFrame> {
-        let frame = self.usable_frames().nth(self.next);
-        self.next += 1;
-        frame
+        // Keep a cursor into the current region, so that each allocation takes constant time instead
+        // of walking the memory map from the start (the bootloader already page-aligns all usable
+        // memory regions, so no alignment code is needed here)
+        while let Some(region) = self.memory_map.get(self.region) {
+            if region.region_type == MemoryRegionType::Usable {
+                let addr = self.next.max(region.range.start_addr());
+                if addr < region.range.end_addr() {
+                    self.next = addr + PAGE_SIZE;
+                    return Some(PhysFrame::containing_address(PhysAddr::new(addr)));
+                }
+            }
+
+            // The current region is exhausted, move on to the next one
+            self.region += 1;
+            self.next = 0;
+        }
+
+        None
     }
 }
 
@@ -77,6 +185,8 @@ unsafe impl FrameAllocator<Size4KiB> for BootInfoFrameAllocator {
 /// aliasing `&mut` references (which is undefined behavior).
 #[must_use]
 pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static> {
+    PHYSICAL_MEMORY_OFFSET.store(physical_memory_offset.as_u64(), Ordering::Relaxed);
+
     unsafe {
         let level_4_table = active_level_4_table(physical_memory_offset);
 
@@ -84,6 +194,90 @@ pub unsafe fn init(physical_memory_offset: VirtAddr) -> OffsetPageTable<'static>
     }
 }
 
+/// Return the virtual address where the physical memory is mapped, or `None` if [`init`] has not
+/// been called yet
+pub fn physical_memory_offset() -> Option<VirtAddr> {
+    match PHYSICAL_MEMORY_OFFSET.load(Ordering::Relaxed) {
+        0 => None,
+        offset => Some(VirtAddr::new(offset)),
+    }
+}
+
+/// Result of a successful [`translate`] call
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Translation {
+    /// Physical address the virtual address maps to
+    pub phys_addr: PhysAddr,
+    /// Size of the page that contains the address (4 KiB, 2 MiB or 1 GiB)
+    pub page_size: u64,
+    /// Effective flags, combining the entries at all levels, with [`walk::PAT`] standing for the PAT
+    /// bit of the entry
+    pub flags: PageTableFlags,
+}
+
+/// Errors that can occur when translating a virtual address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TranslateError {
+    /// The entry for the address is not present in the page table at the passed level
+    NotPresent(PageTableLevel),
+}
+
+/// Translate the passed virtual address to the physical address it maps to, walking the active level
+/// 4 table. Huge pages of 2 MiB and 1 GiB are supported.
+///
+/// ## Errors
+///
+/// Returns a [`TranslateError`] with the level of the first entry that is not present if the address
+/// is not mapped.
+///
+/// ## Panics
+///
+/// Panics if [`init`] has not been called yet.
+pub fn translate(addr: VirtAddr) -> Result<Translation, TranslateError> {
+    let offset = physical_memory_offset().expect("memory not initialized");
+    let (mut table_frame, _) = Cr3::read();
+    let mut level = PageTableLevel::Four;
+    let mut flags = walk::RESTRICTED_FLAGS;
+
+    loop {
+        let table: &PageTable =
+            unsafe { &*(offset + table_frame.start_address().as_u64()).as_ptr() };
+        let entry = &table[addr.page_table_index(level)];
+        if !entry.flags().contains(PageTableFlags::PRESENT) {
+            return Err(TranslateError::NotPresent(level));
+        }
+        flags = walk::effective_flags(entry.flags(), flags);
+
+        // Level 2 and 3 entries map huge pages if the flag is set
+        let huge = matches!(level, PageTableLevel::Three | PageTableLevel::Two)
+            && entry.flags().contains(PageTableFlags::HUGE_PAGE);
+        match level.next_lower_level() {
+            Some(lower_level) if !huge => {
+                table_frame = PhysFrame::containing_address(entry.addr());
+                level = lower_level;
+            }
+            _ => {
+                // The huge page flag only describes the entry size, and the PAT bit is in the address
+                let mut phys_start = entry.addr().as_u64();
+                if huge {
+                    flags -= PageTableFlags::HUGE_PAGE;
+                    if phys_start & walk::HUGE_PAGE_PAT != 0 {
+                        phys_start &= !walk::HUGE_PAGE_PAT;
+                        flags |= walk::PAT;
+                    }
+                }
+
+                let page_size = level.entry_address_space_alignment();
+                return Ok(Translation {
+                    phys_addr: PhysAddr::new(phys_start + (addr.as_u64() & (page_size - 1))),
+                    page_size,
+                    flags,
+                });
+            }
+        }
+    }
+}
+
 /// Private function that returns a mutable reference to the active level 4 table.
 ///
 /// ## Safety
@@ -104,3 +298,21 @@ unsafe fn active_level_4_table(physical_memory_offset: VirtAddr) -> &'static mut
     // Return a mutable reference to the pointer
     unsafe { &mut *page_table_ptr }
 }
+
+/// Hand the passed mapper and frame allocator over to the kernel, so that subsystems such as the
+/// heap can map memory at runtime
+pub fn init_kernel_memory(mapper: OffsetPageTable<'static>, frame_allocator: BuddyFrameAllocator) {
+    *KERNEL_MEMORY.lock() = Some(KernelMemory {
+        mapper,
+        frame_allocator,
+    });
+}
+
+/// Run the passed closure with exclusive access to the kernel memory, or return `None` if it has not
+/// been initialized yet.
+///
+/// The closure runs with interrupts disabled and must not allocate on the heap, because the heap
+/// itself calls this function to grow.
+pub fn with_kernel_memory<R>(f: impl FnOnce(&mut KernelMemory) -> R) -> Option<R> {
+    x86_64::instructions::interrupts::without_interrupts(|| KERNEL_MEMORY.lock().as_mut().map(f))
+}
@@ -0,0 +1,164 @@
+//! Bitmap frame allocator submodule
+
+use core::slice;
+
+use bootloader::bootinfo::MemoryMap;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame, Size4KiB};
+use x86_64::{PhysAddr, VirtAddr};
+
+use super::{FrameError, ManagedMemory, PAGE_SIZE, find_usable_area, frame_range, usable_regions};
+
+/// Number of frames tracked by each bitmap word
+const BITS_PER_WORD: usize = u64::BITS as usize;
+
+/// A [`FrameAllocator`] that tracks every frame with a bit (set when the frame is in use), so that
+/// frames can also be given back
+pub struct BitmapFrameAllocator {
+    bitmap: &'static mut [u64],
+    managed: ManagedMemory,
+    total: usize,
+    free: usize,
+    next: usize,
+}
+
+impl BitmapFrameAllocator {
+    /// Create a [`BitmapFrameAllocator`] from the passed memory map, storing the bitmap itself at the
+    /// start of the first usable region that is large enough. Return `None` if no such region exists.
+    ///
+    /// ## Safety
+    ///
+    /// The caller must guarantee that the passed memory map is valid, that all frames that are marked
+    /// as `USABLE` in it are really unused, and that the complete physical memory is mapped to virtual
+    /// memory at the specified `physical_memory_offset`. In addition, no other frame allocator must
+    /// hand out frames from the same memory map.
+    #[must_use]
+    pub unsafe fn init(memory_map: &MemoryMap, physical_memory_offset: VirtAddr) -> Option<Self> {
+        // Size the bitmap to cover all frames up to the end of the highest usable region
+        let frame_count = usable_regions(memory_map).map(|r| r.end).max()? / PAGE_SIZE;
+        let words = usize::try_from(frame_count).ok()?.div_ceil(BITS_PER_WORD);
+        let bitmap_size = (words * size_of::<u64>()) as u64;
+        let bitmap_start = find_usable_area(memory_map, bitmap_size)?;
+
+        // Start with all frames marked as used
+        let bitmap = unsafe {
+            let ptr: *mut u64 = (physical_memory_offset + bitmap_start.as_u64()).as_mut_ptr();
+            slice::from_raw_parts_mut(ptr, words)
+        };
+        bitmap.fill(u64::MAX);
+
+        let bitmap_area = bitmap_start.as_u64()..bitmap_start.as_u64() + bitmap_size;
+        let mut allocator = Self {
+            bitmap,
+            managed: ManagedMemory::new(memory_map, bitmap_area.clone()),
+            total: 0,
+            free: 0,
+            next: 0,
+        };
+
+        // Mark usable frames as free, except those that hold the bitmap itself
+        let bitmap_frames = frame_range(bitmap_area);
+        for region in usable_regions(memory_map) {
+            for frame in frame_range(region) {
+                allocator.total += 1;
+                if !(bitmap_frames.start..bitmap_frames.end).contains(&frame) {
+                    allocator.set_free(frame);
+                }
+            }
+        }
+
+        Some(allocator)
+    }
+
+    /// Return the number of usable frames managed by the allocator
+    #[must_use]
+    pub const fn total_frames(&self) -> usize {
+        self.total
+    }
+
+    /// Return the number of frames that are currently free
+    #[must_use]
+    pub const fn free_frames(&self) -> usize {
+        self.free
+    }
+
+    /// Return the number of frames that are currently in use (including those that hold the bitmap)
+    #[must_use]
+    pub const fn used_frames(&self) -> usize {
+        self.total - self.free
+    }
+
+    /// Return whether the passed frame is currently in use (frames outside the managed memory are
+    /// always considered in use)
+    #[must_use]
+    pub fn is_used(&self, frame: PhysFrame) -> bool {
+        let (word, bit) = Self::position(frame);
+        self.bitmap.get(word).is_none_or(|w| w & (1 << bit) != 0)
+    }
+
+    /// Give the passed frame back to the allocator.
+    ///
+    /// ## Errors
+    ///
+    /// Returns a [`FrameError`] if the frame is already free or is not managed by the allocator,
+    /// i.e. is outside the usable regions of the memory map or holds the bitmap.
+    ///
+    /// ## Safety
+    ///
+    /// The caller must guarantee that the passed frame is unused.
+    pub unsafe fn free_frame(&mut self, frame: PhysFrame) -> Result<(), FrameError> {
+        let start = frame.start_address().as_u64();
+        if !self.managed.contains(&(start..start + PAGE_SIZE)) {
+            return Err(FrameError::OutOfRange(frame));
+        }
+        if !self.is_used(frame) {
+            return Err(FrameError::DoubleFree(frame));
+        }
+
+        self.set_free(frame);
+        Ok(())
+    }
+
+    /// Return the bitmap word index and bit offset that track the passed frame
+    const fn position(frame: PhysFrame) -> (usize, usize) {
+        let index = (frame.start_address().as_u64() / PAGE_SIZE) as usize;
+        (index / BITS_PER_WORD, index % BITS_PER_WORD)
+    }
+
+    /// Mark the passed frame as free
+    fn set_free(&mut self, frame: PhysFrame) {
+        let (word, bit) = Self::position(frame);
+        self.bitmap[word] &= !(1 << bit);
+        self.free += 1;
+
+        // Make sure that the next search doesn't skip this frame
+        self.next = self.next.min(word);
+    }
+}
+
+unsafe impl FrameAllocator<Size4KiB> for BitmapFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame> {
+        // All words before `next` are full, so the search can start from there
+        let word = self.next
+            + self.bitmap[self.next..]
+                .iter()
+                .position(|&w| w != u64::MAX)?;
+        let bit = self.bitmap[word].trailing_ones() as usize;
+
+        self.bitmap[word] |= 1 << bit;
+        self.free -= 1;
+        self.next = word;
+
+        let addr = (word * BITS_PER_WORD + bit) as u64 * PAGE_SIZE;
+        Some(PhysFrame::containing_address(PhysAddr::new(addr)))
+    }
+}
+
+impl FrameDeallocator<Size4KiB> for BitmapFrameAllocator {
+    /// Deallocate the passed frame, panicking on double frees and on frames that are not managed by
+    /// the allocator
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame) {
+        if let Err(err) = unsafe { self.free_frame(frame) } {
+            panic!("frame deallocation failed: {err:?}");
+        }
+    }
+}
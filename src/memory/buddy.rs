@@ -0,0 +1,278 @@
+//! Buddy frame allocator submodule
+
+use core::slice;
+
+use bootloader::bootinfo::MemoryMap;
+use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PageSize, PhysFrame};
+use x86_64::{PhysAddr, VirtAddr};
+
+use super::{FrameError, ManagedMemory, PAGE_SIZE, find_usable_area, frame_range, usable_regions};
+
+/// Highest supported block order (a block of order `n` spans `2^n` frames, so this is 1 GiB)
+pub const MAX_ORDER: usize = 18;
+
+/// Marker for frames that don't start a free block
+const NOT_FREE: u8 = u8::MAX;
+
+/// Marker for the end of a free list
+const NIL: usize = usize::MAX;
+
+/// Free list node, stored in the first frame of each free block
+struct FreeBlock {
+    prev: usize,
+    next: usize,
+}
+
+/// A [`FrameAllocator`] that hands out naturally aligned, physically contiguous blocks of `2^order`
+/// frames, splitting larger blocks on allocation and coalescing buddies on deallocation
+pub struct BuddyFrameAllocator {
+    physical_memory_offset: VirtAddr,
+    orders: &'static mut [u8],
+    managed: ManagedMemory,
+    free_lists: [usize; MAX_ORDER + 1],
+    total: usize,
+    free: usize,
+}
+
+impl BuddyFrameAllocator {
+    /// Create a [`BuddyFrameAllocator`] from the passed memory map, storing its metadata (one byte
+    /// per frame) at the start of the first usable region that is large enough. Return `None` if no
+    /// such region exists.
+    ///
+    /// ## Safety
+    ///
+    /// The caller must guarantee that the passed memory map is valid, that all frames that are marked
+    /// as `USABLE` in it are really unused, and that the complete physical memory is mapped to virtual
+    /// memory at the specified `physical_memory_offset`. In addition, no other frame allocator must
+    /// hand out frames from the same memory map.
+    #[must_use]
+    pub unsafe fn init(memory_map: &MemoryMap, physical_memory_offset: VirtAddr) -> Option<Self> {
+        // Track the order of the free block starting at each frame, up to the highest usable one
+        let frame_count = usable_regions(memory_map).map(|r| r.end).max()? / PAGE_SIZE;
+        let metadata_start = find_usable_area(memory_map, frame_count)?;
+        let metadata_end = (metadata_start + frame_count).align_up(PAGE_SIZE);
+
+        let orders = unsafe {
+            let ptr: *mut u8 = (physical_memory_offset + metadata_start.as_u64()).as_mut_ptr();
+            slice::from_raw_parts_mut(ptr, usize::try_from(frame_count).ok()?)
+        };
+        orders.fill(NOT_FREE);
+
+        let mut allocator = Self {
+            physical_memory_offset,
+            orders,
+            managed: ManagedMemory::new(memory_map, metadata_start.as_u64()..metadata_end.as_u64()),
+            free_lists: [NIL; MAX_ORDER + 1],
+            total: 0,
+            free: 0,
+        };
+
+        // Hand all usable frames to the allocator, except those that hold the metadata (freeing them
+        // one by one lets the buddies coalesce into the largest possible blocks)
+        for mut region in usable_regions(memory_map) {
+            if region.start == metadata_start.as_u64() {
+                region.start = metadata_end.as_u64();
+            }
+
+            for frame in frame_range(region) {
+                allocator.total += 1;
+                allocator.insert(Self::index(frame), 0);
+            }
+        }
+
+        Some(allocator)
+    }
+
+    /// Return the smallest order whose blocks can hold `size` bytes
+    #[must_use]
+    pub const fn order_for_size(size: u64) -> usize {
+        size.div_ceil(PAGE_SIZE)
+            .next_power_of_two()
+            .trailing_zeros() as usize
+    }
+
+    /// Allocate a block of `2^order` contiguous frames, aligned to its own size, and return its first
+    /// frame. Return `None` if no block that large is available.
+    pub fn allocate(&mut self, order: usize) -> Option<PhysFrame> {
+        // Find the smallest available block that is large enough
+        let found = (order..=MAX_ORDER).find(|&o| self.free_lists[o] != NIL)?;
+        let index = self.free_lists[found];
+        self.remove(index, found);
+
+        // Split it, giving the upper halves back until it has the requested order
+        for o in (order..found).rev() {
+            self.push(index + (1 << o), o);
+        }
+
+        self.free -= 1 << order;
+        Some(Self::frame(index))
+    }
+
+    /// Give back a block of `2^order` frames previously returned by [`Self::allocate`], merging it
+    /// with its free buddies.
+    ///
+    /// ## Errors
+    ///
+    /// Returns a [`FrameError`] if the frame is not aligned to the block size, if the block is not
+    /// entirely in the usable memory managed by the allocator, or if it overlaps a free block.
+    ///
+    /// ## Safety
+    ///
+    /// The caller must guarantee that all frames in the block are unused.
+    pub unsafe fn free(&mut self, frame: PhysFrame, order: usize) -> Result<(), FrameError> {
+        let index = Self::index(frame);
+        if order > MAX_ORDER || index % (1 << order) != 0 {
+            return Err(FrameError::Misaligned(frame));
+        }
+        let start = frame.start_address().as_u64();
+        if !self
+            .managed
+            .contains(&(start..start + (PAGE_SIZE << order)))
+        {
+            return Err(FrameError::OutOfRange(frame));
+        }
+        if self.overlaps_free_block(index, order) {
+            return Err(FrameError::DoubleFree(frame));
+        }
+
+        self.insert(index, order);
+        Ok(())
+    }
+
+    /// Return the number of usable frames managed by the allocator
+    #[must_use]
+    pub const fn total_frames(&self) -> usize {
+        self.total
+    }
+
+    /// Return the number of frames that are currently free
+    #[must_use]
+    pub const fn free_frames(&self) -> usize {
+        self.free
+    }
+
+    /// Return the number of frames that are currently in use
+    #[must_use]
+    pub const fn used_frames(&self) -> usize {
+        self.total - self.free
+    }
+
+    /// Return the number of free blocks of the passed order, which is 0 above [`MAX_ORDER`]
+    #[must_use]
+    pub fn free_blocks(&self, order: usize) -> usize {
+        let Some(&head) = self.free_lists.get(order) else {
+            return 0;
+        };
+        let mut count = 0;
+        let mut index = head;
+        while index != NIL {
+            count += 1;
+            index = unsafe { (*self.node(index)).next };
+        }
+        count
+    }
+
+    /// Return the index of the passed frame in the metadata
+    const fn index(frame: PhysFrame) -> usize {
+        (frame.start_address().as_u64() / PAGE_SIZE) as usize
+    }
+
+    /// Return the frame at the passed index in the metadata
+    const fn frame(index: usize) -> PhysFrame {
+        PhysFrame::containing_address(PhysAddr::new(index as u64 * PAGE_SIZE))
+    }
+
+    /// Return a pointer to the free list node stored in the frame at the passed index
+    fn node(&self, index: usize) -> *mut FreeBlock {
+        (self.physical_memory_offset + index as u64 * PAGE_SIZE).as_mut_ptr()
+    }
+
+    /// Return `true` if the block of the passed order at the passed index overlaps a free block,
+    /// either one that contains it or one inside it
+    fn overlaps_free_block(&self, index: usize, order: usize) -> bool {
+        // A free block of order `o` that contains the block starts at its index rounded down to
+        // a multiple of `2^o`
+        let enclosing = (order..=MAX_ORDER).any(|o| {
+            let start = index & !((1 << o) - 1);
+            self.orders.get(start).is_some_and(|&b| usize::from(b) == o)
+        });
+        enclosing
+            || self.orders[index..index + (1 << order)]
+                .iter()
+                .any(|&b| b != NOT_FREE)
+    }
+
+    /// Add a free block to the allocator, coalescing it with its buddies
+    #[allow(clippy::cast_possible_truncation)] // Orders never exceed `MAX_ORDER`
+    fn insert(&mut self, mut index: usize, mut order: usize) {
+        self.free += 1 << order;
+
+        // The buddy can be merged only if it starts a free block of the same order
+        while order < MAX_ORDER {
+            let buddy = index ^ (1 << order);
+            if self.orders.get(buddy) != Some(&(order as u8)) {
+                break;
+            }
+
+            self.remove(buddy, order);
+            index = index.min(buddy);
+            order += 1;
+        }
+
+        self.push(index, order);
+    }
+
+    /// Push a block onto the free list of the passed order
+    #[allow(clippy::cast_possible_truncation)] // Orders never exceed `MAX_ORDER`
+    fn push(&mut self, index: usize, order: usize) {
+        let head = self.free_lists[order];
+        unsafe {
+            self.node(index).write(FreeBlock {
+                prev: NIL,
+                next: head,
+            });
+            if head != NIL {
+                (*self.node(head)).prev = index;
+            }
+        }
+
+        self.free_lists[order] = index;
+        self.orders[index] = order as u8;
+    }
+
+    /// Unlink a block from the free list of the passed order
+    fn remove(&mut self, index: usize, order: usize) {
+        let FreeBlock { prev, next } = unsafe { self.node(index).read() };
+        if prev == NIL {
+            self.free_lists[order] = next;
+        } else {
+            unsafe { (*self.node(prev)).next = next };
+        }
+        if next != NIL {
+            unsafe { (*self.node(next)).prev = prev };
+        }
+
+        self.orders[index] = NOT_FREE;
+    }
+}
+
+/// Frames of any page size are allocated as blocks of the matching order, so the allocator can back
+/// 4 KiB, 2 MiB and 1 GiB mappings
+unsafe impl<S: PageSize> FrameAllocator<S> for BuddyFrameAllocator {
+    fn allocate_frame(&mut self) -> Option<PhysFrame<S>> {
+        let frame = self.allocate(Self::order_for_size(S::SIZE))?;
+        PhysFrame::from_start_address(frame.start_address()).ok()
+    }
+}
+
+impl<S: PageSize> FrameDeallocator<S> for BuddyFrameAllocator {
+    /// Deallocate the passed frame, panicking on double frees and on frames that are not managed by
+    /// the allocator
+    unsafe fn deallocate_frame(&mut self, frame: PhysFrame<S>) {
+        let order = Self::order_for_size(S::SIZE);
+        let frame = PhysFrame::containing_address(frame.start_address());
+        if let Err(err) = unsafe { self.free(frame, order) } {
+            panic!("frame deallocation failed: {err:?}");
+        }
+    }
+}
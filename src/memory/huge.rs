@@ -0,0 +1,158 @@
+//! Huge page mapping submodule
+
+use core::arch::x86_64::__cpuid;
+
+use x86_64::structures::paging::mapper::{MapToError, UnmapError};
+use x86_64::structures::paging::page::PageRange;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, OffsetPageTable, PageSize, PageTableFlags, Size1GiB,
+    Size4KiB,
+};
+
+use super::with_kernel_memory;
+
+/// CPUID leaf that reports the highest supported extended leaf
+pub(super) const CPUID_MAX_EXTENDED_LEAF: u32 = 0x8000_0000;
+
+/// CPUID leaf that reports the extended processor features
+pub(super) const CPUID_EXTENDED_FEATURES: u32 = 0x8000_0001;
+
+/// Bit in `EDX` of the extended features leaf that reports 1 GiB page support
+const CPUID_PDPE1GB: u32 = 1 << 26;
+
+/// Errors that can occur when mapping or unmapping pages of size `S`
+#[derive(Debug)]
+pub enum HugePageError<S: PageSize> {
+    /// The CPU doesn't support pages of this size
+    Unsupported,
+    /// The kernel memory has not been initialized yet
+    KernelMemoryUnavailable,
+    /// Mapping a page failed
+    Map(MapToError<S>),
+    /// Unmapping a page failed
+    Unmap(UnmapError),
+}
+
+impl<S: PageSize> From<MapToError<S>> for HugePageError<S> {
+    fn from(err: MapToError<S>) -> Self {
+        Self::Map(err)
+    }
+}
+
+impl<S: PageSize> From<UnmapError> for HugePageError<S> {
+    fn from(err: UnmapError) -> Self {
+        Self::Unmap(err)
+    }
+}
+
+/// Return `true` if the CPU supports 1 GiB pages
+#[must_use]
+pub fn supports_1gib_pages() -> bool {
+    __cpuid(CPUID_MAX_EXTENDED_LEAF).eax >= CPUID_EXTENDED_FEATURES
+        && __cpuid(CPUID_EXTENDED_FEATURES).edx & CPUID_PDPE1GB != 0
+}
+
+/// Return `true` if the CPU supports pages of size `S` (4 KiB and 2 MiB pages are always supported
+/// in long mode)
+#[must_use]
+pub fn is_supported<S: PageSize>() -> bool {
+    S::SIZE != Size1GiB::SIZE || supports_1gib_pages()
+}
+
+/// Map the passed pages of size `S` to new frames, which are aligned to the page size.
+///
+/// ## Errors
+///
+/// Returns a [`HugePageError`] if the CPU doesn't support the page size or if [`Mapper::map_to`]
+/// fails. Pages mapped before the failure stay mapped, but the frame of the failed page is freed.
+pub fn map_pages<S: PageSize>(
+    pages: PageRange<S>,
+    flags: PageTableFlags,
+    mapper: &mut impl Mapper<S>,
+    frame_allocator: &mut (impl FrameAllocator<S> + FrameAllocator<Size4KiB> + FrameDeallocator<S>),
+) -> Result<(), HugePageError<S>> {
+    if !is_supported::<S>() {
+        return Err(HugePageError::Unsupported);
+    }
+
+    for page in pages {
+        let frame = FrameAllocator::<S>::allocate_frame(frame_allocator)
+            .ok_or(MapToError::FrameAllocationFailed)?;
+        match unsafe { mapper.map_to(page, frame, flags, frame_allocator) } {
+            Ok(flush) => flush.flush(),
+            Err(err) => {
+                unsafe { frame_allocator.deallocate_frame(frame) };
+                return Err(err.into());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Unmap the passed pages of size `S`, giving their frames back to the frame allocator.
+///
+/// ## Errors
+///
+/// Returns a [`HugePageError`] if [`Mapper::unmap`] fails. Pages unmapped before the failure stay
+/// unmapped.
+///
+/// ## Safety
+///
+/// The caller must guarantee that the pages are not used anymore.
+pub unsafe fn unmap_pages<S: PageSize>(
+    pages: PageRange<S>,
+    mapper: &mut impl Mapper<S>,
+    frame_deallocator: &mut impl FrameDeallocator<S>,
+) -> Result<(), HugePageError<S>> {
+    for page in pages {
+        let (frame, flush) = mapper.unmap(page)?;
+        flush.flush();
+        unsafe { frame_deallocator.deallocate_frame(frame) };
+    }
+
+    Ok(())
+}
+
+/// Map the passed pages of size `S` to new frames, using the global kernel memory.
+///
+/// ## Errors
+///
+/// Returns a [`HugePageError`] if the kernel memory is not available, if the CPU doesn't support the
+/// page size or if mapping a page fails.
+pub fn map_kernel_pages<S: PageSize>(
+    pages: PageRange<S>,
+    flags: PageTableFlags,
+) -> Result<(), HugePageError<S>>
+where
+    OffsetPageTable<'static>: Mapper<S>,
+{
+    with_kernel_memory(|memory| {
+        map_pages(
+            pages,
+            flags,
+            &mut memory.mapper,
+            &mut memory.frame_allocator,
+        )
+    })
+    .ok_or(HugePageError::KernelMemoryUnavailable)?
+}
+
+/// Unmap the passed pages of size `S`, using the global kernel memory.
+///
+/// ## Errors
+///
+/// Returns a [`HugePageError`] if the kernel memory is not available or if unmapping a page fails.
+///
+/// ## Safety
+///
+/// The caller must guarantee that the pages are not used anymore.
+pub unsafe fn unmap_kernel_pages<S: PageSize>(pages: PageRange<S>) -> Result<(), HugePageError<S>>
+where
+    OffsetPageTable<'static>: Mapper<S>,
+{
+    with_kernel_memory(|memory| unsafe {
+        unmap_pages(pages, &mut memory.mapper, &mut memory.frame_allocator)
+    })
+    .ok_or(HugePageError::KernelMemoryUnavailable)?
+}
@@ -0,0 +1,319 @@
+//! Memory-mapped I/O submodule
+
+use core::arch::asm;
+use core::arch::x86_64::__cpuid;
+use core::mem::size_of;
+
+use spin::Once;
+use x86_64::instructions::{interrupts, tlb};
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr4, Cr4Flags};
+use x86_64::registers::model_specific::Msr;
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::{Mapper, Page, PageTableFlags, PhysFrame, Size4KiB};
+use x86_64::{PhysAddr, VirtAddr};
+
+use super::region::{self, Backing, Region, RegionError, RegionKind};
+use super::{PAGE_SIZE, protect, with_kernel_memory};
+
+/// CPUID leaf that reports the processor features
+const CPUID_FEATURES: u32 = 1;
+
+/// Bit in `EDX` of the features leaf that reports PAT support
+const CPUID_PAT: u32 = 1 << 16;
+
+/// Model-specific register that holds the page attribute table
+const IA32_PAT: u32 = 0x277;
+
+/// PAT entry reprogrammed for write-combining (entries 0-3 keep their power-on values, which
+/// `NO_CACHE` and `WRITE_THROUGH` select, and entry 4 is a copy of entry 0)
+const PAT_WC_INDEX: u32 = 4;
+
+/// PAT memory type for write-combining
+const PAT_WRITE_COMBINING: u64 = 0x01;
+
+/// PAT bit of a level 1 page table entry, which shares its position with `HUGE_PAGE` at the other
+/// levels
+const PTE_PAT: PageTableFlags = PageTableFlags::HUGE_PAGE;
+
+/// Result of programming the PAT entry for write-combining, the first time it is needed
+static PAT_INIT: Once<bool> = Once::new();
+
+/// Caching mode of a memory-mapped I/O range
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CacheMode {
+    /// Strong uncacheable, for device registers
+    Uncached,
+    /// Write-through, reads are cached and writes go straight to the device
+    WriteThrough,
+    /// Write-combining, for framebuffers (requires PAT support)
+    WriteCombining,
+}
+
+impl CacheMode {
+    /// Return the page table flags that select this caching mode
+    const fn flags(self) -> PageTableFlags {
+        match self {
+            Self::Uncached => PageTableFlags::NO_CACHE.union(PageTableFlags::WRITE_THROUGH),
+            Self::WriteThrough => PageTableFlags::WRITE_THROUGH,
+            Self::WriteCombining => PTE_PAT,
+        }
+    }
+}
+
+/// Errors that can occur when mapping memory-mapped I/O
+#[derive(Debug)]
+pub enum MmioError {
+    /// The physical range is empty or overflows
+    InvalidRange,
+    /// The caching mode requires PAT support, which the CPU lacks
+    PatUnsupported,
+    /// No kernel region could be reserved for the mapping
+    Region(RegionError),
+    /// The kernel memory has not been initialized yet
+    KernelMemoryUnavailable,
+    /// Mapping a page failed
+    Map(MapToError<Size4KiB>),
+}
+
+impl From<RegionError> for MmioError {
+    fn from(err: RegionError) -> Self {
+        Self::Region(err)
+    }
+}
+
+impl From<MapToError<Size4KiB>> for MmioError {
+    fn from(err: MapToError<Size4KiB>) -> Self {
+        Self::Map(err)
+    }
+}
+
+/// A physical range mapped into a kernel region, which is unmapped and released on drop
+#[derive(Debug)]
+pub struct Mmio {
+    region: Region,
+    phys: PhysAddr,
+    virt: VirtAddr,
+    len: usize,
+    mode: CacheMode,
+}
+
+impl Mmio {
+    /// Return the physical address of the range
+    #[must_use]
+    pub const fn phys_addr(&self) -> PhysAddr {
+        self.phys
+    }
+
+    /// Return the virtual address the range is mapped at
+    #[must_use]
+    pub const fn virt_addr(&self) -> VirtAddr {
+        self.virt
+    }
+
+    /// Return the length of the range in bytes
+    #[must_use]
+    pub const fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Return `true` if the range is empty, which never happens
+    #[must_use]
+    pub const fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Return the caching mode of the mapping
+    #[must_use]
+    pub const fn mode(&self) -> CacheMode {
+        self.mode
+    }
+
+    /// Return a raw pointer to the start of the range
+    #[must_use]
+    pub const fn as_mut_ptr(&self) -> *mut u8 {
+        self.virt.as_mut_ptr()
+    }
+
+    /// Read a value of type `T` at `offset` bytes into the range, with a volatile access.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if the value is out of bounds or misaligned.
+    #[must_use]
+    pub fn read<T: Copy>(&self, offset: usize) -> T {
+        unsafe { self.ptr::<T>(offset).read_volatile() }
+    }
+
+    /// Write a value of type `T` at `offset` bytes into the range, with a volatile access.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if the value is out of bounds or misaligned.
+    pub fn write<T: Copy>(&self, offset: usize, value: T) {
+        unsafe { self.ptr::<T>(offset).write_volatile(value) };
+    }
+
+    /// Return a pointer to a value of type `T` at `offset` bytes into the range, checking bounds and
+    /// alignment
+    fn ptr<T>(&self, offset: usize) -> *mut T {
+        assert!(
+            offset
+                .checked_add(size_of::<T>())
+                .is_some_and(|end| end <= self.len),
+            "MMIO access out of bounds"
+        );
+        let ptr = self.as_mut_ptr().wrapping_add(offset).cast::<T>();
+        assert!(ptr.is_aligned(), "misaligned MMIO access");
+        ptr
+    }
+
+    /// Return the pages of the mapping
+    fn pages(&self) -> impl Iterator<Item = Page> {
+        let start = Page::containing_address(self.region.start);
+        let pages = self.region.size / PAGE_SIZE;
+        (0..pages).map(move |i| start + i)
+    }
+}
+
+impl Drop for Mmio {
+    fn drop(&mut self) {
+        with_kernel_memory(|memory| {
+            for page in self.pages() {
+                // The mapper refuses to unmap level 1 entries with the PAT bit, so clear it first.
+                // The frames belong to the device and are not given back to the frame allocator.
+                if self.mode == CacheMode::WriteCombining {
+                    let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+                    if let Ok(flush) = unsafe { memory.mapper.update_flags(page, flags) } {
+                        flush.ignore();
+                    }
+                }
+                if let Ok((_, flush)) = memory.mapper.unmap(page) {
+                    flush.flush();
+                }
+            }
+        });
+        let _ = region::release(self.region.start);
+    }
+}
+
+/// Return `true` if the CPU supports the page attribute table
+#[must_use]
+pub fn supports_pat() -> bool {
+    __cpuid(CPUID_FEATURES).edx & CPUID_PAT != 0
+}
+
+/// Program the PAT entry used for write-combining, returning `false` if the CPU lacks PAT support
+fn init_pat() -> bool {
+    *PAT_INIT.call_once(|| {
+        if !supports_pat() {
+            return false;
+        }
+
+        let shift = PAT_WC_INDEX * 8;
+        let mut pat = Msr::new(IA32_PAT);
+        interrupts::without_interrupts(|| unsafe {
+            let value = pat.read() & !(0xff << shift);
+            with_caches_disabled(|| pat.write(value | PAT_WRITE_COMBINING << shift));
+        });
+        true
+    })
+}
+
+/// Run the passed closure with the caches disabled and flushed, then flush the caches and the TLBs
+/// again before re-enabling them. This is the sequence that the Intel SDM requires to change the
+/// memory types ("MTRR Considerations in MP Systems"), including the PAT.
+///
+/// ## Safety
+///
+/// Interrupts must be disabled.
+unsafe fn with_caches_disabled(f: impl FnOnce()) {
+    let cr0 = Cr0::read();
+    let cr4 = Cr4::read();
+    unsafe {
+        // Enter the no-fill cache mode and flush the caches
+        Cr0::write((cr0 | Cr0Flags::CACHE_DISABLE) - Cr0Flags::NOT_WRITE_THROUGH);
+        asm!("wbinvd", options(nostack, preserves_flags));
+
+        // Flush the TLBs, including global pages
+        Cr4::write(cr4 - Cr4Flags::PAGE_GLOBAL);
+        tlb::flush_all();
+
+        f();
+
+        // Flush the lines and translations cached with the old memory types, and restore caching
+        asm!("wbinvd", options(nostack, preserves_flags));
+        tlb::flush_all();
+        Cr0::write(cr0);
+        Cr4::write(cr4);
+    }
+}
+
+/// Map `len` bytes of device memory at the passed physical address as uncached, into a kernel
+/// region.
+///
+/// ## Errors
+///
+/// Returns an [`MmioError`] if the range is invalid, if no region can be reserved or if mapping
+/// fails.
+pub fn map_mmio(phys: PhysAddr, len: usize) -> Result<Mmio, MmioError> {
+    map_mmio_with(phys, len, CacheMode::Uncached)
+}
+
+/// Map `len` bytes of device memory at the passed physical address with the passed caching mode,
+/// into a kernel region.
+///
+/// ## Errors
+///
+/// Returns an [`MmioError`] if the range is invalid, if the caching mode is not supported, if no
+/// region can be reserved or if mapping fails.
+pub fn map_mmio_with(phys: PhysAddr, len: usize, mode: CacheMode) -> Result<Mmio, MmioError> {
+    let end = phys
+        .as_u64()
+        .checked_add(len as u64)
+        .and_then(|end| PhysAddr::try_new(end).ok())
+        .filter(|_| len > 0)
+        .ok_or(MmioError::InvalidRange)?;
+    if mode == CacheMode::WriteCombining && !init_pat() {
+        return Err(MmioError::PatUnsupported);
+    }
+
+    // Map whole frames, keeping the offset of the range into the first one
+    let first = PhysFrame::<Size4KiB>::containing_address(phys);
+    let size = end.align_up(PAGE_SIZE) - first.start_address();
+    let region = region::allocate("mmio", size, PAGE_SIZE, RegionKind::Mmio, Backing::Eager)?;
+    let mmio = Mmio {
+        region,
+        phys,
+        virt: region.start + (phys - first.start_address()),
+        len,
+        mode,
+    };
+
+    // Map without the PAT bit, which the mapper rejects, and set it afterwards. On failure, the
+    // handle unmaps the pages mapped so far and releases the region.
+    let flags =
+        PageTableFlags::PRESENT | PageTableFlags::WRITABLE | protect::no_execute() | mode.flags();
+    with_kernel_memory(|memory| {
+        for (i, page) in mmio.pages().enumerate() {
+            let frame = first + i as u64;
+            let map_flags = flags - PTE_PAT;
+            unsafe {
+                memory
+                    .mapper
+                    .map_to(page, frame, map_flags, &mut memory.frame_allocator)?
+                    .flush();
+            }
+            // The page was just mapped, so updating its flags can't fail
+            if flags.contains(PTE_PAT)
+                && let Ok(flush) = unsafe { memory.mapper.update_flags(page, flags) }
+            {
+                flush.flush();
+            }
+        }
+        Ok(())
+    })
+    .ok_or(MmioError::KernelMemoryUnavailable)?
+    .map_err(MmioError::Map)?;
+
+    Ok(mmio)
+}
@@ -0,0 +1,178 @@
+//! Memory protection submodule
+
+use core::arch::x86_64::{__cpuid, __cpuid_count};
+
+use x86_64::VirtAddr;
+use x86_64::instructions::tlb;
+use x86_64::registers::control::{Cr0, Cr0Flags, Cr4, Cr4Flags};
+use x86_64::registers::model_specific::{Efer, EferFlags};
+use x86_64::structures::paging::{PageTable, PageTableFlags};
+
+use super::huge::{CPUID_EXTENDED_FEATURES, CPUID_MAX_EXTENDED_LEAF};
+use super::walk::{RESTRICTED_FLAGS, effective_flags};
+use super::{physical_memory_offset, with_kernel_memory};
+
+/// Bit in `EDX` of the extended features leaf that reports no-execute page support
+const CPUID_NX: u32 = 1 << 20;
+
+/// CPUID leaf that reports the highest supported basic leaf
+const CPUID_MAX_LEAF: u32 = 0;
+
+/// CPUID leaf that reports the structured extended features
+const CPUID_STRUCTURED_FEATURES: u32 = 7;
+
+/// Bit in `EBX` of the structured extended features leaf that reports SMEP support
+const CPUID_SMEP: u32 = 1 << 7;
+
+/// Bit in `EBX` of the structured extended features leaf that reports SMAP support
+const CPUID_SMAP: u32 = 1 << 20;
+
+/// Errors that can occur when enforcing W^X on the kernel mappings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtectError {
+    /// The CPU doesn't support no-execute pages, or they are not enabled
+    NxUnavailable,
+    /// The kernel memory has not been initialized yet
+    KernelMemoryUnavailable,
+}
+
+/// Return `true` if the CPU supports no-execute pages
+#[must_use]
+pub fn supports_nx() -> bool {
+    __cpuid(CPUID_MAX_EXTENDED_LEAF).eax >= CPUID_EXTENDED_FEATURES
+        && __cpuid(CPUID_EXTENDED_FEATURES).edx & CPUID_NX != 0
+}
+
+/// Return `EBX` of the structured extended features leaf, or 0 if the leaf is not supported
+fn structured_features() -> u32 {
+    if __cpuid(CPUID_MAX_LEAF).eax >= CPUID_STRUCTURED_FEATURES {
+        __cpuid_count(CPUID_STRUCTURED_FEATURES, 0).ebx
+    } else {
+        0
+    }
+}
+
+/// Return `true` if the CPU supports supervisor mode execution prevention
+#[must_use]
+pub fn supports_smep() -> bool {
+    structured_features() & CPUID_SMEP != 0
+}
+
+/// Return `true` if the CPU supports supervisor mode access prevention
+#[must_use]
+pub fn supports_smap() -> bool {
+    structured_features() & CPUID_SMAP != 0
+}
+
+/// Enable no-execute pages (`EFER.NXE`) if the CPU supports them, and make read-only pages
+/// read-only for the kernel too (`CR0.WP`).
+///
+/// The bootloader maps the kernel sections according to their ELF segment flags, so once this has
+/// been called the text is read-only and executable, while data, read-only data and bss are not
+/// executable.
+///
+/// SMEP and SMAP are enabled too if the CPU supports them, so that the kernel faults when it
+/// executes user pages, or accesses them outside of the [`user`] accessors.
+///
+/// [`user`]: super::user
+pub fn init() {
+    let mut cr4_flags = Cr4Flags::empty();
+    if supports_smep() {
+        cr4_flags |= Cr4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION;
+    }
+    if supports_smap() {
+        cr4_flags |= Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION;
+    }
+
+    unsafe {
+        if supports_nx() {
+            Efer::update(|flags| *flags |= EferFlags::NO_EXECUTE_ENABLE);
+        }
+        Cr0::update(|flags| *flags |= Cr0Flags::WRITE_PROTECT);
+        Cr4::update(|flags| *flags |= cr4_flags);
+    }
+}
+
+/// Return `true` if supervisor mode access prevention is enabled
+#[must_use]
+pub fn smap_enabled() -> bool {
+    Cr4::read().contains(Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION)
+}
+
+/// Return `true` if no-execute pages are enabled
+#[must_use]
+pub fn nx_enabled() -> bool {
+    Efer::read().contains(EferFlags::NO_EXECUTE_ENABLE)
+}
+
+/// Return [`PageTableFlags::NO_EXECUTE`] if no-execute pages are enabled, or no flags otherwise
+/// (setting the flag with `EFER.NXE` clear makes the entry invalid)
+#[must_use]
+pub fn no_execute() -> PageTableFlags {
+    if nx_enabled() {
+        PageTableFlags::NO_EXECUTE
+    } else {
+        PageTableFlags::empty()
+    }
+}
+
+/// Make every writable mapping of the active page table non-executable, returning the number of
+/// entries changed.
+///
+/// This covers the mappings that the bootloader creates without `NO_EXECUTE`, such as the physical
+/// memory mapping and the boot stack. The kernel text is mapped read-only, so it is not affected.
+///
+/// ## Errors
+///
+/// Returns a [`ProtectError`] if no-execute pages are not enabled or if the kernel memory is not
+/// available.
+pub fn enforce_wx() -> Result<usize, ProtectError> {
+    if !nx_enabled() {
+        return Err(ProtectError::NxUnavailable);
+    }
+    let offset = physical_memory_offset().ok_or(ProtectError::KernelMemoryUnavailable)?;
+
+    let changed = with_kernel_memory(|memory| {
+        let level_4_table = memory.mapper.level_4_table_mut();
+        protect_table(offset, level_4_table, 4, RESTRICTED_FLAGS)
+    })
+    .ok_or(ProtectError::KernelMemoryUnavailable)?;
+
+    tlb::flush_all();
+    Ok(changed)
+}
+
+/// Set `NO_EXECUTE` on the entries of the passed page table (and of its children) that map writable
+/// pages, where `level` is the level of the table and `parent_flags` the effective flags of its
+/// parent entry
+fn protect_table(
+    offset: VirtAddr,
+    table: &mut PageTable,
+    level: u32,
+    parent_flags: PageTableFlags,
+) -> usize {
+    let mut changed = 0;
+
+    for entry in table.iter_mut() {
+        let flags = entry.flags();
+        if !flags.contains(PageTableFlags::PRESENT) {
+            continue;
+        }
+        let effective = effective_flags(flags, parent_flags);
+
+        // Level 1 entries always map pages, level 2 and 3 entries map huge pages if the flag is set
+        if level == 1 || (level < 4 && flags.contains(PageTableFlags::HUGE_PAGE)) {
+            if effective.contains(PageTableFlags::WRITABLE)
+                && !effective.contains(PageTableFlags::NO_EXECUTE)
+            {
+                entry.set_flags(flags | PageTableFlags::NO_EXECUTE);
+                changed += 1;
+            }
+        } else {
+            let table = unsafe { &mut *(offset + entry.addr().as_u64()).as_mut_ptr() };
+            changed += protect_table(offset, table, level - 1, effective);
+        }
+    }
+
+    changed
+}
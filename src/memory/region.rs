@@ -0,0 +1,373 @@
+//! Virtual memory region submodule
+
+use core::ops::Range;
+
+use spin::Mutex;
+use x86_64::VirtAddr;
+use x86_64::instructions::interrupts;
+use x86_64::structures::idt::PageFaultErrorCode;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, Page, PageTableFlags, PhysFrame,
+};
+
+use super::{KERNEL_MEMORY, PAGE_SIZE, physical_memory_offset, protect};
+
+/// Maximum number of regions in a [`RegionTable`]
+pub const MAX_REGIONS: usize = 64;
+
+/// Start of the kernel virtual address space window used by [`allocate`]
+pub const DYNAMIC_START: u64 = 0x0000_1000_0000_0000;
+/// End of the kernel virtual address space window used by [`allocate`]
+pub const DYNAMIC_END: u64 = 0x0000_4000_0000_0000;
+
+/// Region table for the kernel virtual address space
+static REGIONS: Mutex<RegionTable> = Mutex::new(RegionTable::new());
+
+/// Kind of a virtual memory region
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionKind {
+    /// Kernel heap
+    Heap,
+    /// Kernel stacks
+    Stack,
+    /// Memory-mapped I/O
+    Mmio,
+    /// Per-CPU data
+    PerCpu,
+    /// User space memory
+    User,
+    /// Anything else
+    Other,
+}
+
+/// How the pages of a virtual memory region are backed by physical memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backing {
+    /// Pages are mapped by the owner of the region
+    Eager,
+    /// Pages are mapped to zeroed frames with the passed flags on first access, by the page fault
+    /// handler
+    OnDemand(PageTableFlags),
+}
+
+/// A named range of virtual memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    /// Name of the region, for diagnostics
+    pub name: &'static str,
+    /// First address of the region
+    pub start: VirtAddr,
+    /// Size of the region in bytes
+    pub size: u64,
+    /// Kind of the region
+    pub kind: RegionKind,
+    /// How the pages of the region are backed
+    pub backing: Backing,
+}
+
+impl Region {
+    /// Return the address right after the region (as an integer, since it may not be canonical)
+    #[must_use]
+    pub const fn end(&self) -> u64 {
+        self.start.as_u64() + self.size
+    }
+
+    /// Return `true` if the passed address is in the region
+    #[must_use]
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        addr >= self.start && addr.as_u64() < self.end()
+    }
+
+    /// Return `true` if the two regions have at least one address in common
+    #[must_use]
+    pub const fn overlaps(&self, other: &Self) -> bool {
+        self.start.as_u64() < other.end() && other.start.as_u64() < self.end()
+    }
+}
+
+/// Errors that can occur when reserving or releasing regions
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionError {
+    /// The region is empty, or not aligned to the page size
+    InvalidRange,
+    /// The region overlaps the passed existing region
+    Overlap(Region),
+    /// The table has no room for more regions
+    TableFull,
+    /// There's no free range large enough for the region
+    OutOfSpace,
+    /// No region starts at the passed address
+    NotFound(VirtAddr),
+}
+
+/// A table of non-overlapping virtual memory regions, sorted by start address
+pub struct RegionTable {
+    regions: [Option<Region>; MAX_REGIONS],
+    len: usize,
+}
+
+impl Default for RegionTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RegionTable {
+    /// Create a new empty [`RegionTable`]
+    #[must_use]
+    pub const fn new() -> Self {
+        Self {
+            regions: [None; MAX_REGIONS],
+            len: 0,
+        }
+    }
+
+    /// Reserve the region of `size` bytes that starts at the passed address.
+    ///
+    /// ## Errors
+    ///
+    /// Returns a [`RegionError`] if the region is not page-aligned, overlaps an existing region, or
+    /// the table is full.
+    pub fn reserve(
+        &mut self,
+        name: &'static str,
+        start: VirtAddr,
+        size: u64,
+        kind: RegionKind,
+        backing: Backing,
+    ) -> Result<Region, RegionError> {
+        let end = start.as_u64().checked_add(size);
+        if size == 0
+            || !start.is_aligned(PAGE_SIZE)
+            || !size.is_multiple_of(PAGE_SIZE)
+            || end.is_none_or(|end| VirtAddr::try_new(end - 1).is_err())
+        {
+            return Err(RegionError::InvalidRange);
+        }
+        let region = Region {
+            name,
+            start,
+            size,
+            kind,
+            backing,
+        };
+
+        // Find where the region goes, and check it against its neighbors
+        let index = self
+            .iter()
+            .position(|r| r.start > start)
+            .unwrap_or(self.len);
+        let neighbors = [index.checked_sub(1), Some(index)];
+        for other in neighbors.into_iter().flatten().filter_map(|i| self.get(i)) {
+            if other.overlaps(&region) {
+                return Err(RegionError::Overlap(*other));
+            }
+        }
+        if self.len == MAX_REGIONS {
+            return Err(RegionError::TableFull);
+        }
+
+        self.regions[index..=self.len].rotate_right(1);
+        self.regions[index] = Some(region);
+        self.len += 1;
+        Ok(region)
+    }
+
+    /// Reserve a region of `size` bytes aligned to `align` (at least the page size) at the lowest free
+    /// address in the passed window.
+    ///
+    /// ## Errors
+    ///
+    /// Returns a [`RegionError`] if the size or the alignment are invalid, there's no free range large
+    /// enough, or the table is full.
+    pub fn allocate(
+        &mut self,
+        name: &'static str,
+        size: u64,
+        align: u64,
+        kind: RegionKind,
+        backing: Backing,
+        window: Range<VirtAddr>,
+    ) -> Result<Region, RegionError> {
+        if !align.is_power_of_two() {
+            return Err(RegionError::InvalidRange);
+        }
+        let align = align.max(PAGE_SIZE);
+
+        // First fit: try the gap before each region, then the one after the last region
+        let window_end = window.end.as_u64();
+        let mut candidate = window.start.align_up(align).as_u64();
+        for region in self.iter() {
+            let region_start = region.start.as_u64();
+            if region.end() <= candidate {
+                continue;
+            }
+            if region_start >= window_end || region_start.saturating_sub(candidate) >= size {
+                break;
+            }
+            candidate = region.end().next_multiple_of(align);
+        }
+
+        if candidate >= window_end || window_end - candidate < size {
+            return Err(RegionError::OutOfSpace);
+        }
+        let start = VirtAddr::try_new(candidate).map_err(|_| RegionError::OutOfSpace)?;
+        self.reserve(name, start, size, kind, backing)
+    }
+
+    /// Release the region that starts at the passed address, returning it.
+    ///
+    /// ## Errors
+    ///
+    /// Returns [`RegionError::NotFound`] if no region starts at the passed address.
+    pub fn release(&mut self, start: VirtAddr) -> Result<Region, RegionError> {
+        let index = self
+            .iter()
+            .position(|r| r.start == start)
+            .ok_or(RegionError::NotFound(start))?;
+
+        let region = self.regions[index].take();
+        self.regions[index..self.len].rotate_left(1);
+        self.len -= 1;
+        region.ok_or(RegionError::NotFound(start))
+    }
+
+    /// Return the region that contains the passed address, if any
+    #[must_use]
+    pub fn find(&self, addr: VirtAddr) -> Option<Region> {
+        self.iter().find(|r| r.contains(addr)).copied()
+    }
+
+    /// Return an iterator over the regions, sorted by start address
+    pub fn iter(&self) -> impl Iterator<Item = &Region> {
+        self.regions[..self.len].iter().flatten()
+    }
+
+    /// Return the region at the passed index
+    fn get(&self, index: usize) -> Option<&Region> {
+        self.regions.get(index)?.as_ref()
+    }
+}
+
+/// Run the passed closure with exclusive access to the kernel region table, with interrupts disabled
+/// so that interrupt handlers can consult the table too
+fn with_regions<R>(f: impl FnOnce(&mut RegionTable) -> R) -> R {
+    interrupts::without_interrupts(|| f(&mut REGIONS.lock()))
+}
+
+/// Reserve the kernel region of `size` bytes that starts at the passed address.
+///
+/// ## Errors
+///
+/// Returns a [`RegionError`] if the region is not page-aligned, overlaps an existing region, or the
+/// table is full.
+pub fn reserve(
+    name: &'static str,
+    start: VirtAddr,
+    size: u64,
+    kind: RegionKind,
+    backing: Backing,
+) -> Result<Region, RegionError> {
+    with_regions(|regions| regions.reserve(name, start, size, kind, backing))
+}
+
+/// Reserve a kernel region of `size` bytes aligned to `align` at the lowest free address between
+/// [`DYNAMIC_START`] and [`DYNAMIC_END`].
+///
+/// ## Errors
+///
+/// Returns a [`RegionError`] if the size or the alignment are invalid, there's no free range large
+/// enough, or the table is full.
+pub fn allocate(
+    name: &'static str,
+    size: u64,
+    align: u64,
+    kind: RegionKind,
+    backing: Backing,
+) -> Result<Region, RegionError> {
+    let window = VirtAddr::new(DYNAMIC_START)..VirtAddr::new(DYNAMIC_END);
+    with_regions(|regions| regions.allocate(name, size, align, kind, backing, window))
+}
+
+/// Release the kernel region that starts at the passed address, returning it. The caller is
+/// responsible for unmapping its pages, including those mapped on demand.
+///
+/// ## Errors
+///
+/// Returns [`RegionError::NotFound`] if no region starts at the passed address.
+pub fn release(start: VirtAddr) -> Result<Region, RegionError> {
+    with_regions(|regions| regions.release(start))
+}
+
+/// Return the kernel region that contains the passed address, if any
+#[must_use]
+pub fn find(addr: VirtAddr) -> Option<Region> {
+    with_regions(|regions| regions.find(addr))
+}
+
+/// Call the passed closure on each kernel region, sorted by start address. The closure runs with
+/// interrupts disabled and must not reserve or release regions.
+pub fn for_each_region(mut f: impl FnMut(&Region)) {
+    with_regions(|regions| regions.iter().for_each(&mut f));
+}
+
+/// Try to resolve a page fault at the passed address by mapping a zeroed frame, returning `true` if
+/// the faulting access can be retried.
+///
+/// Only faults on pages that are not present in a region backed on demand are resolved, and only if
+/// the access is allowed by the region flags. Since this function is called by the page fault
+/// handler, it gives up instead of waiting for locks held by the interrupted code.
+#[allow(clippy::cast_possible_truncation)] // The page size fits in a `usize`
+pub fn handle_page_fault(addr: VirtAddr, error_code: PageFaultErrorCode) -> bool {
+    if error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+        return false;
+    }
+    let region = REGIONS.try_lock().and_then(|regions| regions.find(addr));
+    let Some(Backing::OnDemand(flags)) = region.map(|r| r.backing) else {
+        return false;
+    };
+
+    // Check the access against the region flags
+    let denied = (error_code.contains(PageFaultErrorCode::CAUSED_BY_WRITE)
+        && !flags.contains(PageTableFlags::WRITABLE))
+        || (error_code.contains(PageFaultErrorCode::USER_MODE)
+            && !flags.contains(PageTableFlags::USER_ACCESSIBLE))
+        || (error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH)
+            && flags.contains(PageTableFlags::NO_EXECUTE));
+    if denied {
+        return false;
+    }
+
+    let Some(mut memory) = KERNEL_MEMORY.try_lock() else {
+        return false;
+    };
+    let (Some(memory), Some(offset)) = (memory.as_mut(), physical_memory_offset()) else {
+        return false;
+    };
+    let Some(frame): Option<PhysFrame> = memory.frame_allocator.allocate_frame() else {
+        return false;
+    };
+
+    // Zero the frame through the physical memory mapping before it becomes visible
+    unsafe {
+        let frame_ptr: *mut u8 = (offset + frame.start_address().as_u64()).as_mut_ptr();
+        frame_ptr.write_bytes(0, PAGE_SIZE as usize);
+    }
+
+    let page = Page::containing_address(addr);
+    // Drop NO_EXECUTE if no-execute pages are disabled, since it would make the entry invalid
+    let flags = (flags - PageTableFlags::NO_EXECUTE)
+        | (flags & protect::no_execute())
+        | PageTableFlags::PRESENT;
+    let frame_allocator = &mut memory.frame_allocator;
+    unsafe { memory.mapper.map_to(page, frame, flags, frame_allocator) }.map_or_else(
+        |_| {
+            unsafe { frame_allocator.deallocate_frame(frame) };
+            false
+        },
+        |flush| {
+            flush.flush();
+            true
+        },
+    )
+}
@@ -0,0 +1,248 @@
+//! Kernel stack allocator submodule
+
+use spin::{Mutex, Once};
+use x86_64::VirtAddr;
+use x86_64::structures::paging::mapper::MapToError;
+use x86_64::structures::paging::page::PageRange;
+use x86_64::structures::paging::{
+    FrameAllocator, FrameDeallocator, Mapper, Page, PageTableFlags, Size4KiB,
+};
+
+use super::region::{self, Backing, Region, RegionError, RegionKind};
+use super::{PAGE_SIZE, protect, with_kernel_memory};
+
+/// Size of the virtual memory region reserved for kernel stacks in bytes
+pub const STACK_REGION_SIZE: u64 = 1024 * 1024 * 1024; // 1 GiB
+
+/// Kernel stack region, allocated in the kernel region table on the first stack allocation
+static STACK_REGION: Once<Region> = Once::new();
+/// Stack allocator for the kernel stack region
+static STACK_ALLOCATOR: Once<Mutex<StackAllocator>> = Once::new();
+
+/// Kernel stack allocation errors
+#[derive(Debug)]
+pub enum StackError {
+    /// The stack region has no room left for the stack
+    RegionExhausted,
+    /// The stack region could not be allocated
+    Region(RegionError),
+    /// The kernel memory has not been initialized yet
+    KernelMemoryUnavailable,
+    /// Mapping the stack pages failed
+    Map(MapToError<Size4KiB>),
+}
+
+impl From<MapToError<Size4KiB>> for StackError {
+    fn from(err: MapToError<Size4KiB>) -> Self {
+        Self::Map(err)
+    }
+}
+
+/// Bounds of an allocated stack
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StackBounds {
+    start: VirtAddr,
+    end: VirtAddr,
+}
+
+impl StackBounds {
+    /// Return the lowest address of the stack
+    #[must_use]
+    pub const fn start(&self) -> VirtAddr {
+        self.start
+    }
+
+    /// Return the address right above the stack, i.e. the initial stack pointer
+    #[must_use]
+    pub const fn end(&self) -> VirtAddr {
+        self.end
+    }
+
+    /// Return the unmapped guard page right below the stack
+    #[must_use]
+    pub fn guard_page(&self) -> Page {
+        Page::containing_address(self.start) - 1
+    }
+
+    /// Return the pages of the stack
+    const fn pages(&self) -> PageRange {
+        Page::range(
+            Page::containing_address(self.start),
+            Page::containing_address(self.end),
+        )
+    }
+}
+
+/// An allocator that hands out stacks from a virtual memory region, leaving an unmapped guard page
+/// below each of them, so that a stack overflow causes a page fault instead of corrupting memory
+pub struct StackAllocator {
+    next: VirtAddr,
+    end: VirtAddr,
+}
+
+impl StackAllocator {
+    /// Create a new [`StackAllocator`] for the virtual memory region of `size` bytes that starts at
+    /// the passed page-aligned address
+    #[must_use]
+    pub const fn new(start: VirtAddr, size: u64) -> Self {
+        Self {
+            next: start,
+            end: VirtAddr::new_truncate(start.as_u64() + size),
+        }
+    }
+
+    /// Allocate a stack of `pages` pages, mapping them to new physical frames.
+    ///
+    /// ## Errors
+    ///
+    /// Returns a [`StackError`] if the region is exhausted or if [`Mapper::map_to`] fails, in which
+    /// case the pages already mapped are unmapped and their frames freed.
+    ///
+    /// ## Panics
+    ///
+    /// Panics if `pages` is zero.
+    pub fn alloc_stack(
+        &mut self,
+        pages: u64,
+        mapper: &mut impl Mapper<Size4KiB>,
+        frame_allocator: &mut (impl FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>),
+    ) -> Result<StackBounds, StackError> {
+        assert!(pages > 0, "stacks must have at least one page");
+
+        // Reserve the guard page and the stack pages
+        let size = (pages + 1) * PAGE_SIZE;
+        if size > self.end - self.next {
+            return Err(StackError::RegionExhausted);
+        }
+        let guard = self.next;
+        self.next += size;
+
+        // Map the stack pages, leaving the guard page unmapped
+        let start = guard + PAGE_SIZE;
+        let end = start + pages * PAGE_SIZE;
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE | protect::no_execute();
+        let stack = StackBounds { start, end };
+        for page in stack.pages() {
+            if let Err(err) = map_page(page, flags, mapper, frame_allocator) {
+                // Give back the pages mapped so far, and the room in the region
+                unmap_pages(
+                    Page::range(stack.pages().start, page),
+                    mapper,
+                    frame_allocator,
+                );
+                self.next = guard;
+                return Err(err.into());
+            }
+        }
+
+        Ok(stack)
+    }
+
+    /// Unmap the passed stack and free its frames. Its room in the region is reclaimed only if it
+    /// is the last stack allocated, so stacks should be freed in reverse allocation order.
+    ///
+    /// ## Safety
+    ///
+    /// The caller must guarantee that the stack was allocated by this allocator and is not in use.
+    pub unsafe fn free_stack(
+        &mut self,
+        stack: StackBounds,
+        mapper: &mut impl Mapper<Size4KiB>,
+        frame_allocator: &mut impl FrameDeallocator<Size4KiB>,
+    ) {
+        unmap_pages(stack.pages(), mapper, frame_allocator);
+
+        // The guard page below the stack is part of its allocation
+        if stack.end == self.next {
+            self.next = stack.guard_page().start_address();
+        }
+    }
+}
+
+/// Map the passed page to a new frame, freeing the frame if the mapping fails
+fn map_page(
+    page: Page,
+    flags: PageTableFlags,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut (impl FrameAllocator<Size4KiB> + FrameDeallocator<Size4KiB>),
+) -> Result<(), MapToError<Size4KiB>> {
+    let frame = frame_allocator
+        .allocate_frame()
+        .ok_or(MapToError::FrameAllocationFailed)?;
+    match unsafe { mapper.map_to(page, frame, flags, frame_allocator) } {
+        Ok(flush) => {
+            flush.flush();
+            Ok(())
+        }
+        Err(err) => {
+            unsafe { frame_allocator.deallocate_frame(frame) };
+            Err(err)
+        }
+    }
+}
+
+/// Unmap the passed pages and free their frames, skipping pages that are not mapped
+fn unmap_pages(
+    pages: PageRange,
+    mapper: &mut impl Mapper<Size4KiB>,
+    frame_allocator: &mut impl FrameDeallocator<Size4KiB>,
+) {
+    for page in pages {
+        if let Ok((frame, flush)) = mapper.unmap(page) {
+            flush.flush();
+            unsafe { frame_allocator.deallocate_frame(frame) };
+        }
+    }
+}
+
+/// Return the kernel stack region, or `None` if no kernel stack has been allocated yet
+#[must_use]
+pub fn stack_region() -> Option<Region> {
+    STACK_REGION.get().copied()
+}
+
+/// Return the allocator for the kernel stack region, allocating the region on the first call
+fn stack_allocator() -> Result<&'static Mutex<StackAllocator>, StackError> {
+    STACK_ALLOCATOR.try_call_once(|| {
+        let (size, kind) = (STACK_REGION_SIZE, RegionKind::Stack);
+        let region = region::allocate("kernel stacks", size, PAGE_SIZE, kind, Backing::Eager)
+            .map_err(StackError::Region)?;
+        STACK_REGION.call_once(|| region);
+        Ok(Mutex::new(StackAllocator::new(region.start, region.size)))
+    })
+}
+
+/// Allocate a kernel stack of `pages` pages in the kernel stack region, using the global kernel
+/// memory.
+///
+/// ## Errors
+///
+/// Returns a [`StackError`] if the stack region can't be allocated, if the kernel memory is not
+/// available, if the region is exhausted or if mapping the stack fails.
+pub fn alloc_stack(pages: u64) -> Result<StackBounds, StackError> {
+    let mut allocator = stack_allocator()?.lock();
+
+    with_kernel_memory(|memory| {
+        allocator.alloc_stack(pages, &mut memory.mapper, &mut memory.frame_allocator)
+    })
+    .ok_or(StackError::KernelMemoryUnavailable)?
+}
+
+/// Unmap a kernel stack allocated with [`alloc_stack`] and free its frames, reclaiming its room in
+/// the kernel stack region if it is the last stack allocated.
+///
+/// ## Errors
+///
+/// Returns [`StackError::KernelMemoryUnavailable`] if the kernel memory is not available.
+///
+/// ## Safety
+///
+/// The caller must guarantee that the stack is not in use, e.g. by the interrupt stack table.
+pub unsafe fn free_stack(stack: StackBounds) -> Result<(), StackError> {
+    let mut allocator = stack_allocator()?.lock();
+
+    with_kernel_memory(|memory| unsafe {
+        allocator.free_stack(stack, &mut memory.mapper, &mut memory.frame_allocator);
+    })
+    .ok_or(StackError::KernelMemoryUnavailable)
+}
@@ -0,0 +1,118 @@
+//! User memory access submodule
+
+use core::arch::{asm, global_asm};
+
+use x86_64::VirtAddr;
+
+use super::protect;
+use super::region::{self, RegionKind};
+
+// Copy `rdx` bytes from `rsi` to `rdi`, returning the number of bytes left in `rax`. If the copy
+// faults, the page fault handler resumes execution at the fixup label, which returns the number of
+// bytes that `rep movsb` had not copied yet.
+global_asm!(
+    ".global rust_os_user_copy",
+    ".global rust_os_user_copy_insn",
+    ".global rust_os_user_copy_fixup",
+    "rust_os_user_copy:",
+    "    mov rcx, rdx",
+    "rust_os_user_copy_insn:",
+    "    rep movsb",
+    "    xor eax, eax",
+    "    ret",
+    "rust_os_user_copy_fixup:",
+    "    mov rax, rcx",
+    "    ret",
+);
+
+unsafe extern "C" {
+    /// Copy routine for user memory, see the assembly above
+    fn rust_os_user_copy(dst: *mut u8, src: *const u8, len: usize) -> usize;
+
+    /// Instruction of the copy routine that may fault
+    static rust_os_user_copy_insn: u8;
+
+    /// Fixup code of the copy routine
+    static rust_os_user_copy_fixup: u8;
+}
+
+/// Errors that can occur when copying from or to user memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UserCopyError {
+    /// The user range is not entirely in a user region
+    InvalidRange,
+    /// The copy faulted after the passed number of bytes
+    Fault {
+        /// Number of bytes copied before the fault
+        copied: usize,
+    },
+}
+
+/// Return the address where execution resumes if the instruction at the passed address faults on
+/// user memory, or `None` if the instruction is not a user memory access
+#[must_use]
+pub fn fixup_address(instruction_pointer: VirtAddr) -> Option<VirtAddr> {
+    let insn = VirtAddr::from_ptr(&raw const rust_os_user_copy_insn);
+    let fixup = VirtAddr::from_ptr(&raw const rust_os_user_copy_fixup);
+    (instruction_pointer == insn).then_some(fixup)
+}
+
+/// Copy the user memory at the passed address to `dst`.
+///
+/// ## Errors
+///
+/// Returns a [`UserCopyError`] if the user range is not in a user region, or if the copy faults,
+/// e.g. on an unmapped page.
+pub fn copy_from_user(dst: &mut [u8], src: VirtAddr) -> Result<(), UserCopyError> {
+    check_user_range(src, dst.len())?;
+    unsafe { user_copy(dst.as_mut_ptr(), src.as_ptr(), dst.len()) }
+}
+
+/// Copy `src` to the user memory at the passed address.
+///
+/// ## Errors
+///
+/// Returns a [`UserCopyError`] if the user range is not in a user region, or if the copy faults,
+/// e.g. on an unmapped page.
+pub fn copy_to_user(dst: VirtAddr, src: &[u8]) -> Result<(), UserCopyError> {
+    check_user_range(dst, src.len())?;
+    unsafe { user_copy(dst.as_mut_ptr(), src.as_ptr(), src.len()) }
+}
+
+/// Check that the `len` bytes at the passed address are in a single user region, so that the kernel
+/// can't be tricked into accessing its own memory
+fn check_user_range(addr: VirtAddr, len: usize) -> Result<(), UserCopyError> {
+    if len == 0 {
+        return Ok(());
+    }
+    let end = addr.as_u64().checked_add(len as u64);
+    let valid = region::find(addr).is_some_and(|region| {
+        region.kind == RegionKind::User && end.is_some_and(|end| end <= region.end())
+    });
+    valid.then_some(()).ok_or(UserCopyError::InvalidRange)
+}
+
+/// Copy `len` bytes from `src` to `dst` with user memory accesses allowed, recovering from faults.
+///
+/// ## Safety
+///
+/// The caller must guarantee that the kernel side of the copy is valid, and that the user side is
+/// not kernel memory.
+unsafe fn user_copy(dst: *mut u8, src: *const u8, len: usize) -> Result<(), UserCopyError> {
+    let smap = protect::smap_enabled();
+    let left = unsafe {
+        if smap {
+            asm!("stac", options(nostack));
+        }
+        let left = rust_os_user_copy(dst, src, len);
+        if smap {
+            asm!("clac", options(nostack));
+        }
+        left
+    };
+
+    match left {
+        0 => Ok(()),
+        left => Err(UserCopyError::Fault { copied: len - left }),
+    }
+}
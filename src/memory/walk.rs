@@ -0,0 +1,237 @@
+//! Page table walker submodule
+
+use core::fmt;
+
+use x86_64::registers::control::Cr3;
+use x86_64::structures::paging::{PageTable, PageTableFlags, PhysFrame};
+use x86_64::{PhysAddr, VirtAddr};
+
+use super::physical_memory_offset;
+use crate::serial_println;
+
+/// Flags that the CPU updates on access, which are ignored in mappings
+const IGNORED_FLAGS: PageTableFlags = PageTableFlags::ACCESSED.union(PageTableFlags::DIRTY);
+
+/// Flag of a [`Mapping`] that reports the PAT bit of its entry.
+///
+/// Level 1 entries have their PAT bit at the position of `HUGE_PAGE`, while huge page entries have it
+/// in the lowest bit of their address field, which the walker moves here.
+pub const PAT: PageTableFlags = PageTableFlags::HUGE_PAGE;
+
+/// PAT bit of a huge page entry
+pub(super) const HUGE_PAGE_PAT: u64 = 1 << 12;
+
+/// Flags that only take effect if they are set at every level of the page table hierarchy
+pub(super) const RESTRICTED_FLAGS: PageTableFlags =
+    PageTableFlags::WRITABLE.union(PageTableFlags::USER_ACCESSIBLE);
+
+/// A contiguous range of virtual memory mapped to a contiguous range of physical memory with the
+/// same effective flags
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mapping {
+    /// First virtual address of the range
+    pub start: VirtAddr,
+    /// First physical address of the range
+    pub phys_start: PhysAddr,
+    /// Size of the range in bytes
+    pub size: u64,
+    /// Effective flags, combining the entries at all levels (e.g. a page is `WRITABLE` only if all
+    /// its parent entries are, and `NO_EXECUTE` if any of them is), with [`PAT`] standing for the PAT
+    /// bit of the entry
+    pub flags: PageTableFlags,
+}
+
+impl Mapping {
+    /// Return `true` if the passed virtual address is in the range
+    #[must_use]
+    pub fn contains(&self, addr: VirtAddr) -> bool {
+        addr >= self.start && addr.as_u64() - self.start.as_u64() < self.size
+    }
+
+    /// Return the physical address the passed virtual address maps to, or `None` if it is not in the
+    /// range
+    #[must_use]
+    pub fn translate(&self, addr: VirtAddr) -> Option<PhysAddr> {
+        self.contains(addr)
+            .then(|| self.phys_start + (addr.as_u64() - self.start.as_u64()))
+    }
+
+    /// Return `true` if the passed mapping directly follows this one, with the same flags
+    fn is_continued_by(&self, next: &Self) -> bool {
+        next.start.as_u64() == self.start.as_u64().wrapping_add(self.size)
+            && next.phys_start.as_u64() == self.phys_start.as_u64() + self.size
+            && next.flags == self.flags
+    }
+}
+
+impl fmt::Display for Mapping {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (size, unit) = human_size(self.size);
+        write!(
+            f,
+            "{:#018x}-{:#018x} -> {:#014x}-{:#014x} {size:>6} {unit:<3} ",
+            self.start.as_u64(),
+            self.start.as_u64() + (self.size - 1),
+            self.phys_start.as_u64(),
+            self.phys_start.as_u64() + (self.size - 1),
+        )?;
+
+        // Print the flag names, skipping PRESENT since it's always set
+        let flags = self.flags - PageTableFlags::PRESENT;
+        for (i, (name, flag)) in flags.iter_names().enumerate() {
+            if i > 0 {
+                f.write_str(" | ")?;
+            }
+            f.write_str(if flag == PAT { "PAT" } else { name })?;
+        }
+        Ok(())
+    }
+}
+
+/// Split the passed size in bytes into a value and the largest binary unit that divides it
+fn human_size(size: u64) -> (u64, &'static str) {
+    [("GiB", 30), ("MiB", 20), ("KiB", 10)]
+        .into_iter()
+        .find(|&(_, shift)| size.trailing_zeros() >= shift)
+        .map_or((size, "B"), |(unit, shift)| (size >> shift, unit))
+}
+
+/// Walk all four levels of the active page table, starting from `Cr3`, and call the passed closure
+/// on each [`Mapping`], in ascending order of virtual address.
+///
+/// Contiguous pages with the same flags are coalesced into a single mapping. The walker doesn't
+/// allocate, so it can be used before the heap is initialized.
+///
+/// ## Panics
+///
+/// Panics if [`memory::init`] has not been called yet.
+///
+/// [`memory::init`]: super::init
+pub fn for_each_mapping(mut f: impl FnMut(&Mapping)) {
+    let offset = physical_memory_offset().expect("memory not initialized");
+    let (level_4_table_frame, _) = Cr3::read();
+
+    let mut pending: Option<Mapping> = None;
+    walk(
+        offset,
+        level_4_table_frame,
+        4,
+        0,
+        RESTRICTED_FLAGS,
+        &mut |mapping| match &mut pending {
+            Some(current) if current.is_continued_by(&mapping) => current.size += mapping.size,
+            _ => {
+                if let Some(current) = pending.replace(mapping) {
+                    f(&current);
+                }
+            }
+        },
+    );
+
+    if let Some(current) = pending {
+        f(&current);
+    }
+}
+
+/// Return the [`Mapping`] that contains the passed virtual address, or `None` if it isn't mapped.
+///
+/// ## Panics
+///
+/// Panics if [`memory::init`] has not been called yet.
+///
+/// [`memory::init`]: super::init
+#[must_use]
+pub fn find_mapping(addr: VirtAddr) -> Option<Mapping> {
+    let mut found = None;
+    for_each_mapping(|mapping| {
+        if mapping.contains(addr) {
+            found = Some(*mapping);
+        }
+    });
+    found
+}
+
+/// Print all mappings of the active page table to serial.
+///
+/// ## Panics
+///
+/// Panics if [`memory::init`] has not been called yet.
+///
+/// [`memory::init`]: super::init
+pub fn dump() {
+    let (level_4_table_frame, _) = Cr3::read();
+    serial_println!(
+        "Page table at {:#x}:",
+        level_4_table_frame.start_address().as_u64()
+    );
+
+    let mut count = 0;
+    for_each_mapping(|mapping| {
+        serial_println!("  {}", mapping);
+        count += 1;
+    });
+    serial_println!("{} mappings", count);
+}
+
+/// Combine the flags of a page table entry with the effective flags of its parent entry (starting
+/// with [`RESTRICTED_FLAGS`] at the top level)
+pub(super) fn effective_flags(
+    flags: PageTableFlags,
+    parent_flags: PageTableFlags,
+) -> PageTableFlags {
+    (flags - RESTRICTED_FLAGS)
+        | (flags & parent_flags & RESTRICTED_FLAGS)
+        | (parent_flags & PageTableFlags::NO_EXECUTE)
+}
+
+/// Call the passed closure on each page mapped by the page table in the passed frame, where `level`
+/// is the level of the table, `base` the first virtual address it covers, and `parent_flags` the
+/// effective flags of its parent entry
+fn walk<F: FnMut(Mapping)>(
+    offset: VirtAddr,
+    frame: PhysFrame,
+    level: u32,
+    base: u64,
+    parent_flags: PageTableFlags,
+    f: &mut F,
+) {
+    let table: &PageTable = unsafe { &*(offset + frame.start_address().as_u64()).as_ptr() };
+    let shift = 12 + 9 * (level - 1);
+
+    for (index, entry) in table.iter().enumerate() {
+        let flags = entry.flags();
+        if !flags.contains(PageTableFlags::PRESENT) {
+            continue;
+        }
+
+        let addr = base | (index as u64) << shift;
+        let flags = effective_flags(flags, parent_flags);
+
+        // Level 1 entries always map pages, level 2 and 3 entries map huge pages if the flag is set
+        if level == 1 {
+            f(Mapping {
+                start: VirtAddr::new_truncate(addr),
+                phys_start: entry.addr(),
+                size: 1 << shift,
+                flags: flags - IGNORED_FLAGS,
+            });
+        } else if level < 4 && flags.contains(PageTableFlags::HUGE_PAGE) {
+            // The huge page flag only describes the entry size, and the PAT bit is in the address
+            let phys_start = entry.addr().as_u64();
+            let pat = if phys_start & HUGE_PAGE_PAT == 0 {
+                PageTableFlags::empty()
+            } else {
+                PAT
+            };
+            f(Mapping {
+                start: VirtAddr::new_truncate(addr),
+                phys_start: PhysAddr::new(phys_start & !HUGE_PAGE_PAT),
+                size: 1 << shift,
+                flags: (flags - IGNORED_FLAGS - PageTableFlags::HUGE_PAGE) | pat,
+            });
+        } else {
+            let frame = PhysFrame::containing_address(entry.addr());
+            walk(offset, frame, level - 1, addr, flags, f);
+        }
+    }
+}
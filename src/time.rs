@@ -0,0 +1,235 @@
+//! Time module
+//!
+//! Channel 0 of the PIT raises the timer interrupt at [`frequency`] Hz, and the interrupt handler
+//! counts the ticks since boot. It also runs the callbacks of expired timers, which are scheduled
+//! with [`schedule_after`].
+//!
+//! Finer time is read from a [`ClockSource`], chosen by [`init_clock`] among the invariant TSC, the
+//! HPET and the PIT ticks, in that order of preference.
+
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use core::time::Duration;
+
+use spin::Once;
+use x86_64::instructions::interrupts;
+
+use self::hpet::Hpet;
+use self::pit::PitClock;
+use self::timer::TimerId;
+use self::tsc::Tsc;
+
+pub mod hpet;
+pub mod pit;
+pub mod rtc;
+pub mod timer;
+pub mod tsc;
+
+/// Timer interrupt frequency set by [`init`] in Hz
+pub const DEFAULT_FREQUENCY: u32 = 1000;
+
+/// Number of nanoseconds in a second
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+/// Number of timer ticks since boot
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// PIT divisor of the current tick period (0 until [`init`] has been called)
+static DIVISOR: AtomicU32 = AtomicU32::new(0);
+
+/// Uptime in nanoseconds when the tick period last changed
+static BASE_NANOS: AtomicU64 = AtomicU64::new(0);
+/// Number of timer ticks when the tick period last changed
+static BASE_TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// HPET, once [`init_clock`] has found one
+static HPET: Once<Hpet> = Once::new();
+/// Calibrated TSC, once [`init_clock`] has been called
+static TSC: Once<Tsc> = Once::new();
+/// Clock source read by [`now`], once [`init_clock`] has been called
+static CLOCK: Once<Clock> = Once::new();
+
+/// A source of monotonic time
+pub trait ClockSource: Sync {
+    /// Return the name of the clock source
+    fn name(&self) -> &'static str;
+
+    /// Return the time elapsed since an arbitrary origin, which differs between sources
+    fn read(&self) -> Duration;
+
+    /// Return the smallest interval the clock source can measure
+    fn resolution(&self) -> Duration;
+}
+
+/// The clock source chosen by [`init_clock`], with the reading that matches the uptime at the time
+#[derive(Clone, Copy)]
+struct Clock {
+    source: &'static dyn ClockSource,
+    base_read: Duration,
+    base_uptime: Duration,
+}
+
+/// Errors that can occur when changing the timer frequency
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeError {
+    /// The PIT can't be programmed to the passed frequency in Hz
+    InvalidFrequency(u32),
+    /// [`timer::MAX_TIMERS`] timers are already pending
+    TooManyTimers,
+}
+
+/// Program the PIT to raise the timer interrupt at [`DEFAULT_FREQUENCY`] Hz
+#[allow(clippy::missing_panics_doc)] // The default frequency is in range
+pub fn init() {
+    set_frequency(DEFAULT_FREQUENCY).expect("default timer frequency is valid");
+}
+
+/// Program the PIT to raise the timer interrupt at the passed frequency in Hz, returning the
+/// frequency actually obtained. The uptime stays monotonic across frequency changes.
+///
+/// ## Errors
+///
+/// Returns [`TimeError::InvalidFrequency`] if the frequency is lower than [`pit::MIN_FREQUENCY`] or
+/// higher than [`pit::PIT_FREQUENCY`].
+pub fn set_frequency(frequency: u32) -> Result<u32, TimeError> {
+    let divisor = pit::divisor_for(frequency).ok_or(TimeError::InvalidFrequency(frequency))?;
+
+    interrupts::without_interrupts(|| {
+        BASE_NANOS.store(uptime_nanos(), Ordering::Relaxed);
+        BASE_TICKS.store(ticks(), Ordering::Relaxed);
+        DIVISOR.store(divisor, Ordering::Relaxed);
+        pit::set_divisor(divisor);
+    });
+
+    Ok(pit::frequency_for(divisor))
+}
+
+/// Return the timer interrupt frequency in Hz, or 0 if [`init`] has not been called
+#[must_use]
+pub fn frequency() -> u32 {
+    match DIVISOR.load(Ordering::Relaxed) {
+        0 => 0,
+        divisor => pit::frequency_for(divisor),
+    }
+}
+
+/// Return the number of timer ticks since boot
+#[must_use]
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Return the time elapsed since [`init`] was called, with the resolution of a tick
+#[must_use]
+pub fn uptime() -> Duration {
+    Duration::from_nanos(uptime_nanos())
+}
+
+/// Return the uptime in nanoseconds
+fn uptime_nanos() -> u64 {
+    let divisor = u64::from(DIVISOR.load(Ordering::Relaxed));
+    let ticks = ticks() - BASE_TICKS.load(Ordering::Relaxed);
+    let nanos =
+        u128::from(ticks) * u128::from(divisor * NANOS_PER_SEC) / u128::from(pit::PIT_FREQUENCY);
+    BASE_NANOS.load(Ordering::Relaxed) + u64::try_from(nanos).unwrap_or(u64::MAX)
+}
+
+/// Halt the CPU until at least the passed duration has elapsed, with the resolution of a tick.
+///
+/// ## Panics
+///
+/// Panics if interrupts are disabled, since the ticks would never advance.
+pub fn sleep(duration: Duration) {
+    assert!(
+        interrupts::are_enabled(),
+        "sleeping with interrupts disabled"
+    );
+
+    let deadline = deadline_after(duration);
+    while uptime_nanos() < deadline {
+        x86_64::instructions::hlt();
+    }
+}
+
+/// Schedule the passed callback to run from the timer interrupt handler once at least the passed
+/// duration has elapsed, on a later tick than the current one.
+///
+/// The callback runs with interrupts disabled, so it must be short and must not allocate. It may
+/// schedule another timer.
+///
+/// ## Errors
+///
+/// Returns [`TimeError::TooManyTimers`] if [`timer::MAX_TIMERS`] timers are already pending.
+pub fn schedule_after(duration: Duration, callback: fn()) -> Result<TimerId, TimeError> {
+    timer::schedule_at(
+        deadline_after(duration.max(Duration::from_nanos(1))),
+        callback,
+    )
+    .ok_or(TimeError::TooManyTimers)
+}
+
+/// Return the uptime in nanoseconds once the passed duration has elapsed
+fn deadline_after(duration: Duration) -> u64 {
+    let nanos = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+    uptime_nanos().saturating_add(nanos)
+}
+
+/// Count a timer tick and run the expired timers, called by the timer interrupt handler
+pub(crate) fn tick() {
+    TICKS.fetch_add(1, Ordering::Relaxed);
+    timer::run_expired(uptime_nanos());
+}
+
+/// Probe the HPET, calibrate the TSC and choose the clock source used by [`now`]: the TSC if it is
+/// invariant, else the HPET, else the PIT ticks. Returns the name of the chosen source.
+///
+/// This must be called after [`init`] and once kernel memory is initialized, since the HPET is
+/// memory-mapped. Further calls return the source chosen by the first one.
+pub fn init_clock() -> &'static str {
+    let clock = CLOCK.call_once(|| {
+        if let Ok(hpet) = Hpet::probe() {
+            HPET.call_once(|| hpet);
+        }
+        if let Some(tsc) = Tsc::calibrate(HPET.get()) {
+            TSC.call_once(|| tsc);
+        }
+
+        let source: &'static dyn ClockSource = match (TSC.get(), HPET.get()) {
+            (Some(tsc), _) if tsc.is_invariant() => tsc,
+            (_, Some(hpet)) => hpet,
+            _ => &PitClock,
+        };
+        interrupts::without_interrupts(|| Clock {
+            source,
+            base_read: source.read(),
+            base_uptime: uptime(),
+        })
+    });
+    clock.source.name()
+}
+
+/// Return the clock source used by [`now`]
+#[must_use]
+pub fn clock_source() -> &'static dyn ClockSource {
+    CLOCK.get().map_or(&PitClock, |clock| clock.source)
+}
+
+/// Return the HPET, or `None` if [`init_clock`] has not been called or found none
+#[must_use]
+pub fn hpet() -> Option<&'static Hpet> {
+    HPET.get()
+}
+
+/// Return the calibrated TSC, or `None` if [`init_clock`] has not been called
+#[must_use]
+pub fn tsc() -> Option<&'static Tsc> {
+    TSC.get()
+}
+
+/// Return the time elapsed since [`init`] was called, with the resolution of the clock source
+/// chosen by [`init_clock`], or of a tick before that
+#[must_use]
+pub fn now() -> Duration {
+    CLOCK.get().map_or_else(uptime, |clock| {
+        clock.base_uptime + clock.source.read().saturating_sub(clock.base_read)
+    })
+}
@@ -0,0 +1,115 @@
+//! High Precision Event Timer submodule
+
+use core::time::Duration;
+
+use x86_64::PhysAddr;
+
+use super::ClockSource;
+use crate::memory::mmio::{self, Mmio, MmioError};
+
+/// Physical address of the HPET on PC-compatible machines
+pub const HPET_BASE: u64 = 0xfed0_0000;
+
+/// Size of the HPET register block in bytes
+const HPET_SIZE: usize = 0x400;
+
+/// General capabilities and ID register
+const CAPABILITIES: usize = 0x00;
+/// General configuration register
+const CONFIGURATION: usize = 0x10;
+/// Main counter value register
+const MAIN_COUNTER: usize = 0xf0;
+
+/// Bit of [`CAPABILITIES`] that reports a 64-bit main counter
+const COUNT_SIZE_CAP: u64 = 1 << 13;
+/// Bit of [`CONFIGURATION`] that starts the main counter
+const ENABLE_CNF: u64 = 1 << 0;
+
+/// Longest counter period allowed by the specification in femtoseconds (100 ns)
+const MAX_PERIOD_FS: u64 = 100_000_000;
+
+/// Number of femtoseconds in a nanosecond
+const FS_PER_NS: u64 = 1_000_000;
+
+/// Errors that can occur when probing the HPET
+#[derive(Debug)]
+pub enum HpetError {
+    /// No HPET answers at the probed address
+    NotPresent,
+    /// The main counter is 32-bit wide, so it would wrap around within minutes
+    Counter32Bit,
+    /// Mapping the HPET registers failed
+    Mmio(MmioError),
+}
+
+impl From<MmioError> for HpetError {
+    fn from(err: MmioError) -> Self {
+        Self::Mmio(err)
+    }
+}
+
+/// A High Precision Event Timer, whose main counter is used as a clock source
+#[derive(Debug)]
+pub struct Hpet {
+    mmio: Mmio,
+    period_fs: u64,
+}
+
+impl Hpet {
+    /// Probe the HPET at [`HPET_BASE`] and start its main counter.
+    ///
+    /// ## Errors
+    ///
+    /// Returns an [`HpetError`] if the registers can't be mapped or don't look like an HPET.
+    pub fn probe() -> Result<Self, HpetError> {
+        let mmio = mmio::map_mmio(PhysAddr::new(HPET_BASE), HPET_SIZE)?;
+
+        // Missing devices read as all ones, and the vendor ID is never zero
+        let capabilities: u64 = mmio.read(CAPABILITIES);
+        let vendor_id = (capabilities >> 16) & 0xffff;
+        let period_fs = capabilities >> 32;
+        if vendor_id == 0 || vendor_id == 0xffff || !(1..=MAX_PERIOD_FS).contains(&period_fs) {
+            return Err(HpetError::NotPresent);
+        }
+        if capabilities & COUNT_SIZE_CAP == 0 {
+            return Err(HpetError::Counter32Bit);
+        }
+
+        let configuration: u64 = mmio.read(CONFIGURATION);
+        mmio.write(CONFIGURATION, configuration | ENABLE_CNF);
+        Ok(Self { mmio, period_fs })
+    }
+
+    /// Return the value of the main counter
+    #[must_use]
+    pub fn counter(&self) -> u64 {
+        self.mmio.read(MAIN_COUNTER)
+    }
+
+    /// Return the period of the main counter in femtoseconds
+    #[must_use]
+    pub const fn period_fs(&self) -> u64 {
+        self.period_fs
+    }
+
+    /// Convert the passed number of counter ticks to nanoseconds
+    #[must_use]
+    pub fn ticks_to_nanos(&self, ticks: u64) -> u64 {
+        let nanos = u128::from(ticks) * u128::from(self.period_fs) / u128::from(FS_PER_NS);
+        u64::try_from(nanos).unwrap_or(u64::MAX)
+    }
+}
+
+impl ClockSource for Hpet {
+    fn name(&self) -> &'static str {
+        "hpet"
+    }
+
+    fn read(&self) -> Duration {
+        Duration::from_nanos(self.ticks_to_nanos(self.counter()))
+    }
+
+    fn resolution(&self) -> Duration {
+        Duration::from_nanos(self.period_fs.div_ceil(FS_PER_NS))
+    }
+}
@@ -0,0 +1,120 @@
+//! Programmable Interval Timer submodule
+
+use core::hint;
+use core::time::Duration;
+
+use spin::Mutex;
+use x86_64::instructions::port::Port;
+
+use super::{ClockSource, NANOS_PER_SEC};
+
+/// Frequency of the PIT input clock in Hz
+pub const PIT_FREQUENCY: u32 = 1_193_182;
+
+/// Lowest frequency channel 0 can be programmed to in Hz (a divisor of 65536)
+pub const MIN_FREQUENCY: u32 = PIT_FREQUENCY.div_ceil(1 << 16);
+
+/// Channel 0 data port
+const CHANNEL_0_PORT: u16 = 0x40;
+/// Channel 2 data port
+const CHANNEL_2_PORT: u16 = 0x42;
+/// Mode/command register port
+const COMMAND_PORT: u16 = 0x43;
+/// Port that controls the channel 2 gate and the PC speaker, and reports the channel 2 output
+const CHANNEL_2_CONTROL_PORT: u16 = 0x61;
+
+/// Command that selects channel 0, the lobyte/hibyte access mode and mode 2 (rate generator)
+const CHANNEL_0_RATE_GENERATOR: u8 = 0b0011_0100;
+/// Command that selects channel 2, the lobyte/hibyte access mode and mode 0 (interrupt on terminal
+/// count)
+const CHANNEL_2_ONE_SHOT: u8 = 0b1011_0000;
+
+/// Bit of [`CHANNEL_2_CONTROL_PORT`] that enables counting on channel 2
+const CHANNEL_2_GATE: u8 = 1 << 0;
+/// Bit of [`CHANNEL_2_CONTROL_PORT`] that connects channel 2 to the PC speaker
+const SPEAKER_ENABLE: u8 = 1 << 1;
+/// Bit of [`CHANNEL_2_CONTROL_PORT`] that reports the channel 2 output
+const CHANNEL_2_OUTPUT: u8 = 1 << 5;
+
+/// PIT ports, which must be written in sequence
+static PORTS: Mutex<()> = Mutex::new(());
+
+/// Return the divisor that makes channel 0 fire closest to the passed frequency, or `None` if the
+/// frequency is out of range
+#[must_use]
+pub fn divisor_for(frequency: u32) -> Option<u32> {
+    (MIN_FREQUENCY..=PIT_FREQUENCY)
+        .contains(&frequency)
+        .then(|| ((PIT_FREQUENCY + frequency / 2) / frequency).clamp(1, 1 << 16))
+}
+
+/// Return the frequency channel 0 fires at with the passed divisor in Hz, rounded to the nearest
+/// integer
+#[must_use]
+pub const fn frequency_for(divisor: u32) -> u32 {
+    (PIT_FREQUENCY + divisor / 2) / divisor
+}
+
+/// Clock source that counts timer interrupts, with the resolution of a tick
+#[derive(Debug)]
+pub struct PitClock;
+
+impl ClockSource for PitClock {
+    fn name(&self) -> &'static str {
+        "pit"
+    }
+
+    fn read(&self) -> Duration {
+        super::uptime()
+    }
+
+    fn resolution(&self) -> Duration {
+        match super::frequency() {
+            0 => Duration::ZERO,
+            frequency => Duration::from_nanos(NANOS_PER_SEC / u64::from(frequency)),
+        }
+    }
+}
+
+/// Program channel 0 as a rate generator with the passed divisor, between 1 and 65536.
+///
+/// ## Panics
+///
+/// Panics if the divisor is out of range.
+pub fn set_divisor(divisor: u32) {
+    assert!((1..=1 << 16).contains(&divisor), "invalid PIT divisor");
+
+    // A divisor of 65536 is written as 0
+    let [low, high, ..] = (divisor & 0xffff).to_le_bytes();
+    let _guard = PORTS.lock();
+    unsafe {
+        Port::<u8>::new(COMMAND_PORT).write(CHANNEL_0_RATE_GENERATOR);
+        let mut data = Port::<u8>::new(CHANNEL_0_PORT);
+        data.write(low);
+        data.write(high);
+    }
+}
+
+/// Busy-wait for the passed number of PIT input clock cycles on channel 2, which doesn't depend on
+/// interrupts and leaves the timer interrupt alone
+pub fn wait_cycles(cycles: u16) {
+    let [low, high] = cycles.max(1).to_le_bytes();
+    let _guard = PORTS.lock();
+    unsafe {
+        // Enable counting, without sound
+        let mut control = Port::<u8>::new(CHANNEL_2_CONTROL_PORT);
+        let saved = control.read();
+        control.write((saved & !SPEAKER_ENABLE) | CHANNEL_2_GATE);
+
+        // The output goes high when the count reaches zero
+        Port::<u8>::new(COMMAND_PORT).write(CHANNEL_2_ONE_SHOT);
+        let mut data = Port::<u8>::new(CHANNEL_2_PORT);
+        data.write(low);
+        data.write(high);
+        while control.read() & CHANNEL_2_OUTPUT == 0 {
+            hint::spin_loop();
+        }
+
+        control.write(saved);
+    }
+}
@@ -0,0 +1,296 @@
+//! CMOS Real-Time Clock submodule
+//!
+//! The RTC keeps the wall-clock date and time while the machine is off. Its registers may be in BCD
+//! or binary and the hours in 12 or 24-hour format, as reported by status register B.
+
+use core::fmt;
+use core::hint;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+use x86_64::instructions::port::Port;
+
+use super::TimeError;
+use crate::interrupts::{InterruptIndex, unmask_pic_irq};
+
+/// CMOS register select port, whose bit 7 disables NMIs
+const INDEX_PORT: u16 = 0x70;
+/// CMOS data port
+const DATA_PORT: u16 = 0x71;
+/// Bit of [`INDEX_PORT`] that disables NMIs
+const NMI_DISABLE: u8 = 1 << 7;
+
+/// Seconds register
+const SECONDS: u8 = 0x00;
+/// Minutes register
+const MINUTES: u8 = 0x02;
+/// Hours register
+const HOURS: u8 = 0x04;
+/// Day of month register
+const DAY: u8 = 0x07;
+/// Month register
+const MONTH: u8 = 0x08;
+/// Year register, modulo 100
+const YEAR: u8 = 0x09;
+/// Century register, at the location most firmwares report in the ACPI FADT
+const CENTURY: u8 = 0x32;
+
+/// Status register A, which holds the update-in-progress flag and the periodic rate
+const STATUS_A: u8 = 0x0a;
+/// Status register B, which holds the data format and interrupt enables
+const STATUS_B: u8 = 0x0b;
+/// Status register C, which reports the interrupt causes and must be read to acknowledge them
+const STATUS_C: u8 = 0x0c;
+/// Status register D, selected when idle
+const STATUS_D: u8 = 0x0d;
+
+/// Bit of [`STATUS_A`] set while the RTC updates its registers
+const UPDATE_IN_PROGRESS: u8 = 1 << 7;
+/// Mask of the periodic rate in [`STATUS_A`]
+const RATE_MASK: u8 = 0x0f;
+/// Bit of [`STATUS_B`] set when the hours are in 24-hour format
+const HOURS_24: u8 = 1 << 1;
+/// Bit of [`STATUS_B`] set when the registers are in binary rather than BCD
+const BINARY: u8 = 1 << 2;
+/// Bit of [`STATUS_B`] that enables the periodic interrupt
+const PERIODIC_INTERRUPT: u8 = 1 << 6;
+/// Bit of [`STATUS_C`] set when the periodic interrupt fired
+const PERIODIC_FLAG: u8 = 1 << 6;
+/// Bit of the hours register set for PM hours in 12-hour format
+const HOURS_PM: u8 = 1 << 7;
+
+/// Frequency of the RTC oscillator in Hz, which the periodic rate divides
+const OSCILLATOR_FREQUENCY: u32 = 32768;
+
+/// Number of seconds in a day
+const SECS_PER_DAY: u64 = 86400;
+
+/// CMOS ports, which must be written in sequence
+static PORTS: Mutex<()> = Mutex::new(());
+
+/// Number of periodic interrupts since they were enabled
+static PERIODIC_TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// A date and time in UTC, as kept by the RTC
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    /// Year, e.g. 2024
+    pub year: u16,
+    /// Month, from 1 to 12
+    pub month: u8,
+    /// Day of month, from 1 to 31
+    pub day: u8,
+    /// Hour, from 0 to 23
+    pub hour: u8,
+    /// Minute, from 0 to 59
+    pub minute: u8,
+    /// Second, from 0 to 59
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Return the number of seconds between the Unix epoch and the date and time, or 0 if it is
+    /// before the epoch
+    #[must_use]
+    pub fn unix_timestamp(&self) -> u64 {
+        let days = days_from_civil(self.year, self.month, self.day);
+        let secs =
+            u64::from(self.hour) * 3600 + u64::from(self.minute) * 60 + u64::from(self.second);
+        u64::try_from(days).map_or(0, |days| days * SECS_PER_DAY + secs)
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+/// Raw values of the date and time registers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Registers {
+    second: u8,
+    minute: u8,
+    hour: u8,
+    day: u8,
+    month: u8,
+    year: u8,
+    century: u8,
+}
+
+/// Read the current date and time from the RTC.
+///
+/// The registers are read outside of updates, until two reads in a row agree. If the century
+/// register doesn't hold a plausible value, the 21st century is assumed.
+#[must_use]
+pub fn read() -> DateTime {
+    let mut registers = read_registers();
+    loop {
+        let again = read_registers();
+        if again == registers {
+            break;
+        }
+        registers = again;
+    }
+    decode(registers, with_cmos(|| read_register(STATUS_B)))
+}
+
+/// Return the number of seconds since the Unix epoch, according to the RTC
+#[must_use]
+pub fn unix_timestamp() -> u64 {
+    read().unix_timestamp()
+}
+
+/// Enable the RTC periodic interrupt on IRQ 8 at the passed frequency in Hz, a power of two from 2
+/// to 8192, unmasking it on the PICs if they are in use.
+///
+/// ## Errors
+///
+/// Returns [`TimeError::InvalidFrequency`] if the frequency is not a power of two in range.
+pub fn enable_periodic_interrupt(frequency: u32) -> Result<(), TimeError> {
+    if !frequency.is_power_of_two() || !(2..=8192).contains(&frequency) {
+        return Err(TimeError::InvalidFrequency(frequency));
+    }
+    // The frequency is 32768 >> (rate - 1)
+    #[allow(clippy::cast_possible_truncation)] // The rate is between 3 and 15
+    let rate = (OSCILLATOR_FREQUENCY / frequency).trailing_zeros() as u8 + 1;
+
+    with_cmos(|| {
+        let status_a = read_register(STATUS_A);
+        write_register(STATUS_A, (status_a & !RATE_MASK) | rate);
+        let status_b = read_register(STATUS_B);
+        write_register(STATUS_B, status_b | PERIODIC_INTERRUPT);
+
+        // A pending interrupt would keep IRQ 8 from firing again
+        read_register(STATUS_C);
+    });
+    unmask_pic_irq(InterruptIndex::Rtc);
+    Ok(())
+}
+
+/// Disable the RTC periodic interrupt
+pub fn disable_periodic_interrupt() {
+    with_cmos(|| {
+        let status_b = read_register(STATUS_B);
+        write_register(STATUS_B, status_b & !PERIODIC_INTERRUPT);
+    });
+}
+
+/// Return the frequency of the RTC periodic interrupt in Hz, or 0 if the rate is off
+#[must_use]
+pub fn periodic_frequency() -> u32 {
+    match with_cmos(|| read_register(STATUS_A)) & RATE_MASK {
+        0 => 0,
+        rate => OSCILLATOR_FREQUENCY >> (rate - 1),
+    }
+}
+
+/// Return the number of RTC periodic interrupts received since boot
+#[must_use]
+pub fn periodic_ticks() -> u64 {
+    PERIODIC_TICKS.load(Ordering::Relaxed)
+}
+
+/// Acknowledge an RTC interrupt and count it, called by the IRQ 8 interrupt handler
+pub(crate) fn handle_interrupt() {
+    let causes = with_cmos(|| read_register(STATUS_C));
+    if causes & PERIODIC_FLAG != 0 {
+        PERIODIC_TICKS.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Read the date and time registers once no update is in progress
+fn read_registers() -> Registers {
+    while with_cmos(|| read_register(STATUS_A)) & UPDATE_IN_PROGRESS != 0 {
+        hint::spin_loop();
+    }
+    with_cmos(|| Registers {
+        second: read_register(SECONDS),
+        minute: read_register(MINUTES),
+        hour: read_register(HOURS),
+        day: read_register(DAY),
+        month: read_register(MONTH),
+        year: read_register(YEAR),
+        century: read_register(CENTURY),
+    })
+}
+
+/// Convert the passed raw register values to a date and time, according to the passed value of
+/// status register B
+fn decode(registers: Registers, status_b: u8) -> DateTime {
+    let convert = |value: u8| {
+        if status_b & BINARY == 0 {
+            (value >> 4) * 10 + (value & 0x0f)
+        } else {
+            value
+        }
+    };
+
+    // In 12-hour format, midnight is 12 AM and the PM bit is set on top of the encoded value
+    let mut hour = convert(registers.hour & !HOURS_PM);
+    if status_b & HOURS_24 == 0 {
+        hour %= 12;
+        if registers.hour & HOURS_PM != 0 {
+            hour += 12;
+        }
+    }
+
+    let century = match convert(registers.century) {
+        century @ 19..=99 => u16::from(century),
+        _ => 20,
+    };
+    DateTime {
+        year: century * 100 + u16::from(convert(registers.year)),
+        month: convert(registers.month),
+        day: convert(registers.day),
+        hour,
+        minute: convert(registers.minute),
+        second: convert(registers.second),
+    }
+}
+
+/// Return the number of days between the Unix epoch and the passed date in the proleptic Gregorian
+/// calendar
+fn days_from_civil(year: u16, month: u8, day: u8) -> i64 {
+    // Count years from March, so that the leap day is the last day of the year
+    let year = i64::from(year) - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let month = i64::from(month);
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Run the passed closure with exclusive access to the CMOS and with interrupts and NMIs disabled
+fn with_cmos<R>(f: impl FnOnce() -> R) -> R {
+    interrupts::without_interrupts(|| {
+        let _guard = PORTS.lock();
+        let result = f();
+
+        // Re-enable NMIs
+        unsafe { Port::<u8>::new(INDEX_PORT).write(STATUS_D) };
+        result
+    })
+}
+
+/// Read the CMOS register with the passed index, with NMIs disabled
+fn read_register(reg: u8) -> u8 {
+    unsafe {
+        Port::<u8>::new(INDEX_PORT).write(NMI_DISABLE | reg);
+        Port::<u8>::new(DATA_PORT).read()
+    }
+}
+
+/// Write the CMOS register with the passed index, with NMIs disabled
+fn write_register(reg: u8, value: u8) {
+    unsafe {
+        Port::<u8>::new(INDEX_PORT).write(NMI_DISABLE | reg);
+        Port::<u8>::new(DATA_PORT).write(value);
+    }
+}
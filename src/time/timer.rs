@@ -0,0 +1,193 @@
+//! Timer queue submodule
+//!
+//! Pending timers are kept in a fixed-size binary min-heap ordered by deadline, so that the timer
+//! interrupt handler only has to look at the root to know whether a timer has expired, and never
+//! allocates.
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+/// Maximum number of pending timers
+pub const MAX_TIMERS: usize = 64;
+
+/// Pending timers
+static TIMERS: Mutex<TimerQueue> = Mutex::new(TimerQueue::new());
+
+/// Deadline of the earliest pending timer in nanoseconds of uptime, or `u64::MAX` if there is none,
+/// so that the timer interrupt handler doesn't take the lock on every tick
+static NEXT_DEADLINE: AtomicU64 = AtomicU64::new(u64::MAX);
+
+/// ID of the next scheduled timer
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Identifier of a scheduled timer, used to cancel it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TimerId(u64);
+
+/// A pending timer
+#[derive(Debug, Clone, Copy)]
+struct Timer {
+    /// Uptime in nanoseconds at which the timer expires
+    deadline: u64,
+    id: TimerId,
+    callback: fn(),
+}
+
+impl Timer {
+    /// Return the key the queue is ordered by, timers with the same deadline running in the order
+    /// they were scheduled
+    const fn key(&self) -> (u64, TimerId) {
+        (self.deadline, self.id)
+    }
+}
+
+/// A binary min-heap of timers ordered by deadline
+struct TimerQueue {
+    heap: [Option<Timer>; MAX_TIMERS],
+    len: usize,
+}
+
+impl TimerQueue {
+    /// Create an empty timer queue
+    const fn new() -> Self {
+        Self {
+            heap: [None; MAX_TIMERS],
+            len: 0,
+        }
+    }
+
+    /// Return the timer at the passed index of the heap
+    const fn get(&self, index: usize) -> Timer {
+        self.heap[index].expect("index is in the heap")
+    }
+
+    /// Return the deadline of the earliest timer, or `u64::MAX` if the queue is empty
+    fn next_deadline(&self) -> u64 {
+        self.heap[0].map_or(u64::MAX, |timer| timer.deadline)
+    }
+
+    /// Add a timer to the queue, or return `false` if it is full
+    fn push(&mut self, timer: Timer) -> bool {
+        if self.len == MAX_TIMERS {
+            return false;
+        }
+        self.heap[self.len] = Some(timer);
+        self.len += 1;
+        self.sift_up(self.len - 1);
+        true
+    }
+
+    /// Remove the timer at the passed index of the heap and return it
+    fn remove(&mut self, index: usize) -> Timer {
+        let timer = self.get(index);
+        self.len -= 1;
+        self.heap.swap(index, self.len);
+        self.heap[self.len] = None;
+        if index < self.len {
+            self.sift_down(index);
+            self.sift_up(index);
+        }
+        timer
+    }
+
+    /// Remove the earliest timer if it expires at or before the passed deadline
+    fn pop_expired(&mut self, now: u64) -> Option<Timer> {
+        (self.next_deadline() <= now).then(|| self.remove(0))
+    }
+
+    /// Remove the timer with the passed ID, returning `true` if it was pending
+    fn cancel(&mut self, id: TimerId) -> bool {
+        let index = (0..self.len).find(|&i| self.get(i).id == id);
+        index.map(|i| self.remove(i)).is_some()
+    }
+
+    /// Move the timer at the passed index up until its parent expires before it
+    fn sift_up(&mut self, mut index: usize) {
+        while index > 0 {
+            let parent = (index - 1) / 2;
+            if self.get(parent).key() <= self.get(index).key() {
+                break;
+            }
+            self.heap.swap(parent, index);
+            index = parent;
+        }
+    }
+
+    /// Move the timer at the passed index down until its children expire after it
+    fn sift_down(&mut self, mut index: usize) {
+        loop {
+            let smallest = [2 * index + 1, 2 * index + 2]
+                .into_iter()
+                .filter(|&child| child < self.len)
+                .fold(index, |smallest, child| {
+                    if self.get(child).key() < self.get(smallest).key() {
+                        child
+                    } else {
+                        smallest
+                    }
+                });
+            if smallest == index {
+                break;
+            }
+            self.heap.swap(smallest, index);
+            index = smallest;
+        }
+    }
+}
+
+/// Schedule the passed callback to run from the timer interrupt handler once the uptime reaches
+/// the passed deadline in nanoseconds, or return `None` if [`MAX_TIMERS`] timers are pending
+pub(super) fn schedule_at(deadline: u64, callback: fn()) -> Option<TimerId> {
+    let id = TimerId(NEXT_ID.fetch_add(1, Ordering::Relaxed));
+    interrupts::without_interrupts(|| {
+        let mut timers = TIMERS.lock();
+        let pushed = timers.push(Timer {
+            deadline,
+            id,
+            callback,
+        });
+        NEXT_DEADLINE.store(timers.next_deadline(), Ordering::Relaxed);
+        pushed.then_some(id)
+    })
+}
+
+/// Cancel the timer with the passed ID, returning `true` if it had not run yet
+#[must_use]
+pub fn cancel(id: TimerId) -> bool {
+    interrupts::without_interrupts(|| {
+        let mut timers = TIMERS.lock();
+        let cancelled = timers.cancel(id);
+        NEXT_DEADLINE.store(timers.next_deadline(), Ordering::Relaxed);
+        cancelled
+    })
+}
+
+/// Return the number of pending timers
+#[must_use]
+pub fn pending() -> usize {
+    interrupts::without_interrupts(|| TIMERS.lock().len)
+}
+
+/// Run the callbacks of the timers that expire at or before the passed uptime in nanoseconds,
+/// called by the timer interrupt handler
+pub(super) fn run_expired(now: u64) {
+    if NEXT_DEADLINE.load(Ordering::Relaxed) > now {
+        return;
+    }
+
+    // The lock is released while a callback runs, so that it can schedule another timer
+    loop {
+        let expired = {
+            let mut timers = TIMERS.lock();
+            let expired = timers.pop_expired(now);
+            NEXT_DEADLINE.store(timers.next_deadline(), Ordering::Relaxed);
+            expired
+        };
+        match expired {
+            Some(timer) => (timer.callback)(),
+            None => break,
+        }
+    }
+}
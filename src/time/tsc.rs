@@ -0,0 +1,118 @@
+//! Time Stamp Counter submodule
+
+use core::arch::x86_64::{__cpuid, _rdtsc};
+use core::hint;
+use core::time::Duration;
+
+use x86_64::instructions::interrupts;
+
+use super::hpet::Hpet;
+use super::{ClockSource, NANOS_PER_SEC, pit};
+
+/// CPUID leaf that reports the highest supported extended leaf
+const CPUID_MAX_EXTENDED_LEAF: u32 = 0x8000_0000;
+/// CPUID leaf that reports the advanced power management features
+const CPUID_POWER_MANAGEMENT: u32 = 0x8000_0007;
+/// Bit in `EDX` of the power management leaf that reports an invariant TSC
+const CPUID_INVARIANT_TSC: u32 = 1 << 8;
+
+/// Duration of the calibration in milliseconds
+const CALIBRATION_MS: u64 = 10;
+
+/// Return the current value of the time stamp counter
+#[must_use]
+pub fn rdtsc() -> u64 {
+    unsafe { _rdtsc() }
+}
+
+/// Return `true` if the TSC runs at a constant rate in all power states, so that it can be used to
+/// measure time
+#[must_use]
+pub fn is_invariant() -> bool {
+    __cpuid(CPUID_MAX_EXTENDED_LEAF).eax >= CPUID_POWER_MANAGEMENT
+        && __cpuid(CPUID_POWER_MANAGEMENT).edx & CPUID_INVARIANT_TSC != 0
+}
+
+/// The time stamp counter, calibrated as a clock source
+#[derive(Debug)]
+pub struct Tsc {
+    frequency: u64,
+    invariant: bool,
+}
+
+impl Tsc {
+    /// Measure the TSC frequency against the passed HPET if any, or against the PIT otherwise.
+    /// Interrupts are disabled during the measurement, which takes about 10 ms.
+    ///
+    /// Returns `None` if the TSC didn't advance.
+    #[must_use]
+    pub fn calibrate(hpet: Option<&Hpet>) -> Option<Self> {
+        let (cycles, nanos) = interrupts::without_interrupts(|| {
+            hpet.map_or_else(measure_with_pit, measure_with_hpet)
+        });
+
+        let frequency = u128::from(cycles) * u128::from(NANOS_PER_SEC) / u128::from(nanos);
+        let frequency = u64::try_from(frequency).ok().filter(|&f| f > 0)?;
+        Some(Self {
+            frequency,
+            invariant: is_invariant(),
+        })
+    }
+
+    /// Return the calibrated frequency of the TSC in Hz
+    #[must_use]
+    pub const fn frequency(&self) -> u64 {
+        self.frequency
+    }
+
+    /// Return `true` if the TSC is invariant
+    #[must_use]
+    pub const fn is_invariant(&self) -> bool {
+        self.invariant
+    }
+
+    /// Convert the passed number of TSC cycles to nanoseconds
+    #[must_use]
+    pub fn cycles_to_nanos(&self, cycles: u64) -> u64 {
+        let nanos = u128::from(cycles) * u128::from(NANOS_PER_SEC) / u128::from(self.frequency);
+        u64::try_from(nanos).unwrap_or(u64::MAX)
+    }
+}
+
+impl ClockSource for Tsc {
+    fn name(&self) -> &'static str {
+        "tsc"
+    }
+
+    fn read(&self) -> Duration {
+        Duration::from_nanos(self.cycles_to_nanos(rdtsc()))
+    }
+
+    fn resolution(&self) -> Duration {
+        Duration::from_nanos(NANOS_PER_SEC.div_ceil(self.frequency))
+    }
+}
+
+/// Return the number of TSC cycles and nanoseconds elapsed while busy-waiting on the HPET
+fn measure_with_hpet(hpet: &Hpet) -> (u64, u64) {
+    let start_counter = hpet.counter();
+    let start = rdtsc();
+    let mut elapsed = 0;
+    while hpet.ticks_to_nanos(elapsed) < CALIBRATION_MS * 1_000_000 {
+        hint::spin_loop();
+        elapsed = hpet.counter() - start_counter;
+    }
+    (rdtsc() - start, hpet.ticks_to_nanos(elapsed))
+}
+
+/// Return the number of TSC cycles and nanoseconds elapsed while busy-waiting on PIT channel 2
+fn measure_with_pit() -> (u64, u64) {
+    let pit_cycles = u64::from(pit::PIT_FREQUENCY) * CALIBRATION_MS / 1000;
+    let start = rdtsc();
+    pit::wait_cycles(u16::try_from(pit_cycles).unwrap_or(u16::MAX));
+    let cycles = rdtsc() - start;
+    (
+        cycles,
+        pit_cycles * NANOS_PER_SEC / u64::from(pit::PIT_FREQUENCY),
+    )
+}
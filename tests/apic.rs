@@ -0,0 +1,103 @@
+//! Integration test for the local APIC and I/O APIC
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+
+use bootloader::{BootInfo, entry_point};
+use rust_os::hlt_loop;
+
+entry_point!(main);
+
+/// Panic handler
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+/// Integration test entry point
+//noinspection RsUnresolvedPath
+fn main(boot_info: &'static BootInfo) -> ! {
+    // Initializations
+    rust_os::test_init(boot_info);
+
+    test_main();
+    hlt_loop();
+}
+
+#[cfg(test)]
+mod tests {
+    use core::arch::x86_64::__cpuid;
+
+    use rust_os::acpi;
+    use rust_os::interrupts::{self, apic};
+    use x86_64::PhysAddr;
+    use x86_64::instructions::hlt;
+    use x86_64::instructions::port::Port;
+
+    #[test_case]
+    fn madt_is_found() {
+        let madt = acpi::madt().expect("MADT not found");
+        assert_eq!(madt.io_apic.address, PhysAddr::new(0xfec0_0000));
+        assert_eq!(madt.io_apic.gsi_base, 0);
+
+        // QEMU connects the PIT to the global system interrupt 2
+        let timer = madt.isa_override(0).expect("PIT override not reported");
+        assert_eq!(timer.gsi, 2);
+        assert!(!timer.active_low && !timer.level_triggered);
+        assert!(madt.overrides().all(|o| o.irq < 16));
+    }
+
+    #[test_case]
+    fn switch_to_apic() {
+        let mode = interrupts::init_apic().expect("APIC initialization failed");
+        assert!(apic::is_enabled());
+        assert!(matches!(
+            interrupts::init_apic(),
+            Err(apic::ApicError::AlreadyInitialized)
+        ));
+
+        let local_apic = apic::local_apic().expect("local APIC not initialized");
+        assert_eq!(local_apic.mode(), mode);
+        assert_eq!(mode == apic::ApicMode::X2Apic, apic::supports_x2apic());
+        if mode == apic::ApicMode::XApic {
+            assert_eq!(local_apic.id(), __cpuid(1).ebx >> 24);
+        }
+
+        // The PICs are masked off
+        let masks = unsafe { [Port::<u8>::new(0x21).read(), Port::<u8>::new(0xa1).read()] };
+        assert_eq!(masks, [0xff, 0xff]);
+    }
+
+    #[test_case]
+    fn isa_irqs_are_routed() {
+        // The keyboard has no override, so it is identity-mapped
+        assert_eq!(apic::isa_irq_to_gsi(0), Ok(2));
+        assert_eq!(apic::isa_irq_to_gsi(1), Ok(1));
+
+        let (timer, keyboard, unused) = apic::with_io_apic(|io_apic| {
+            assert!(io_apic.entries() >= 16);
+            (
+                io_apic.redirection(2),
+                io_apic.redirection(1),
+                io_apic.redirection(3),
+            )
+        })
+        .expect("I/O APIC not initialized");
+        assert_eq!(timer & 0x1_00ff, 32);
+        assert_eq!(keyboard & 0x1_00ff, 33);
+        assert_ne!(unused & 0x1_0000, 0);
+    }
+
+    #[test_case]
+    fn timer_interrupts_are_delivered() {
+        // Each halt returns only after an interrupt, and hangs the test otherwise
+        for _ in 0..3 {
+            hlt();
+        }
+    }
+}
@@ -0,0 +1,202 @@
+//! Integration test for buddy frame allocation
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+
+use bootloader::bootinfo::MemoryRegionType;
+use bootloader::{BootInfo, entry_point};
+use rust_os::hlt_loop;
+use rust_os::memory::buddy::BuddyFrameAllocator;
+use spin::{Mutex, Once};
+use x86_64::structures::paging::PhysFrame;
+use x86_64::{PhysAddr, VirtAddr};
+
+entry_point!(main);
+
+/// Physical memory offset, used by the test cases to access the allocated frames
+static PHYS_MEM_OFFSET: Once<VirtAddr> = Once::new();
+
+/// Frame allocator shared by the test cases
+static FRAME_ALLOCATOR: Once<Mutex<BuddyFrameAllocator>> = Once::new();
+
+/// A frame of the kernel image, which the bootloader reserved
+static KERNEL_FRAME: Once<PhysFrame> = Once::new();
+
+/// Panic handler
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+/// Integration test entry point
+//noinspection RsUnresolvedPath
+fn main(boot_info: &'static BootInfo) -> ! {
+    // Initializations
+    rust_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    PHYS_MEM_OFFSET.call_once(|| phys_mem_offset);
+    FRAME_ALLOCATOR.call_once(|| {
+        let frame_allocator =
+            unsafe { BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+        Mutex::new(frame_allocator.expect("frame allocator initialization failed"))
+    });
+    KERNEL_FRAME.call_once(|| {
+        let kernel = boot_info
+            .memory_map
+            .iter()
+            .find(|r| r.region_type == MemoryRegionType::Kernel)
+            .expect("no kernel region in the memory map");
+        PhysFrame::containing_address(PhysAddr::new(kernel.range.start_addr()))
+    });
+
+    test_main();
+    hlt_loop();
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_os::memory::FrameError;
+    use rust_os::memory::buddy::{BuddyFrameAllocator, MAX_ORDER};
+    use x86_64::structures::paging::{FrameAllocator, FrameDeallocator, PhysFrame};
+
+    use super::{FRAME_ALLOCATOR, KERNEL_FRAME, PHYS_MEM_OFFSET};
+
+    /// Size of a 2 MiB block in bytes
+    const SIZE_2MIB: usize = 2 * 1024 * 1024;
+
+    /// Order of a 2 MiB block
+    const ORDER_2MIB: usize = 9;
+
+    #[test_case]
+    fn order_for_size() {
+        assert_eq!(BuddyFrameAllocator::order_for_size(1), 0);
+        assert_eq!(BuddyFrameAllocator::order_for_size(4096), 0);
+        assert_eq!(BuddyFrameAllocator::order_for_size(4097), 1);
+        assert_eq!(
+            BuddyFrameAllocator::order_for_size(SIZE_2MIB as u64),
+            ORDER_2MIB
+        );
+    }
+
+    #[test_case]
+    fn single_frames() {
+        let mut frame_allocator = FRAME_ALLOCATOR.get().unwrap().lock();
+        let free = frame_allocator.free_frames();
+
+        let frame_1: PhysFrame = frame_allocator.allocate_frame().expect("out of frames");
+        let frame_2: PhysFrame = frame_allocator.allocate_frame().expect("out of frames");
+        assert_ne!(frame_1, frame_2);
+        assert_eq!(frame_allocator.free_frames(), free - 2);
+
+        unsafe {
+            frame_allocator.deallocate_frame(frame_1);
+            frame_allocator.deallocate_frame(frame_2);
+        }
+        assert_eq!(frame_allocator.free_frames(), free);
+    }
+
+    #[test_case]
+    fn aligned_contiguous_block() {
+        let mut frame_allocator = FRAME_ALLOCATOR.get().unwrap().lock();
+
+        let frame = frame_allocator
+            .allocate(ORDER_2MIB)
+            .expect("out of 2 MiB blocks");
+        assert!(frame.start_address().is_aligned(SIZE_2MIB as u64));
+
+        // The whole block must be usable memory
+        let virt = *PHYS_MEM_OFFSET.get().unwrap() + frame.start_address().as_u64();
+        unsafe {
+            let block = core::slice::from_raw_parts_mut(virt.as_mut_ptr::<u8>(), SIZE_2MIB);
+            block.fill(0xaa);
+            assert!(block.iter().all(|&b| b == 0xaa));
+        }
+
+        unsafe { frame_allocator.free(frame, ORDER_2MIB) }.expect("free failed");
+    }
+
+    #[test_case]
+    fn buddies_coalesce() {
+        let mut frame_allocator = FRAME_ALLOCATOR.get().unwrap().lock();
+        let free_blocks = frame_allocator.free_blocks(ORDER_2MIB);
+
+        // Split a 2 MiB block into frames, then give them back one by one
+        let frame = frame_allocator
+            .allocate(ORDER_2MIB)
+            .expect("out of 2 MiB blocks");
+        for i in 0..1 << ORDER_2MIB {
+            unsafe { frame_allocator.free(frame + i, 0) }.expect("free failed");
+        }
+
+        assert_eq!(frame_allocator.free_blocks(ORDER_2MIB), free_blocks);
+    }
+
+    #[test_case]
+    fn free_blocks_above_max_order() {
+        let frame_allocator = FRAME_ALLOCATOR.get().unwrap().lock();
+        assert_eq!(frame_allocator.free_blocks(MAX_ORDER + 1), 0);
+        assert_eq!(frame_allocator.free_blocks(usize::MAX), 0);
+    }
+
+    #[test_case]
+    fn invalid_free() {
+        let mut frame_allocator = FRAME_ALLOCATOR.get().unwrap().lock();
+
+        let frame = frame_allocator.allocate(1).expect("out of frames");
+        assert_eq!(
+            unsafe { frame_allocator.free(frame + 1, 1) },
+            Err(FrameError::Misaligned(frame + 1))
+        );
+        assert_eq!(unsafe { frame_allocator.free(frame, 1) }, Ok(()));
+        assert_eq!(
+            unsafe { frame_allocator.free(frame, 1) },
+            Err(FrameError::DoubleFree(frame))
+        );
+    }
+
+    #[test_case]
+    fn free_inside_free_block() {
+        let mut frame_allocator = FRAME_ALLOCATOR.get().unwrap().lock();
+        let free = frame_allocator.free_frames();
+
+        // Once the block is free again, none of its frames or sub-blocks can be freed
+        let frame = frame_allocator.allocate(3).expect("out of frames");
+        unsafe { frame_allocator.free(frame, 3) }.expect("free failed");
+        for (offset, order) in [(1, 0), (4, 2), (0, 1)] {
+            assert_eq!(
+                unsafe { frame_allocator.free(frame + offset, order) },
+                Err(FrameError::DoubleFree(frame + offset))
+            );
+        }
+
+        // Nor can a larger block that contains a free one
+        let frame = frame_allocator.allocate(3).expect("out of frames");
+        unsafe { frame_allocator.free(frame + 2, 0) }.expect("free failed");
+        assert_eq!(
+            unsafe { frame_allocator.free(frame, 3) },
+            Err(FrameError::DoubleFree(frame))
+        );
+        for i in [0, 1, 3, 4, 5, 6, 7] {
+            unsafe { frame_allocator.free(frame + i, 0) }.expect("free failed");
+        }
+        assert_eq!(frame_allocator.free_frames(), free);
+    }
+
+    #[test_case]
+    fn free_reserved_frame() {
+        let mut frame_allocator = FRAME_ALLOCATOR.get().unwrap().lock();
+        let frame = *KERNEL_FRAME.get().unwrap();
+        let free = frame_allocator.free_frames();
+
+        assert_eq!(
+            unsafe { frame_allocator.free(frame, 0) },
+            Err(FrameError::OutOfRange(frame))
+        );
+        assert_eq!(frame_allocator.free_frames(), free);
+    }
+}
@@ -0,0 +1,102 @@
+//! Integration test for the clock sources and `time::now`
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+
+use bootloader::{BootInfo, entry_point};
+use rust_os::hlt_loop;
+
+entry_point!(main);
+
+/// Panic handler
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+/// Integration test entry point
+//noinspection RsUnresolvedPath
+fn main(boot_info: &'static BootInfo) -> ! {
+    // Initializations
+    rust_os::test_init(boot_info);
+    rust_os::time::init_clock();
+
+    test_main();
+    hlt_loop();
+}
+
+#[cfg(test)]
+mod tests {
+    use core::time::Duration;
+
+    use rust_os::time::pit::PitClock;
+    use rust_os::time::{self, ClockSource, tsc};
+    use x86_64::instructions::hlt;
+
+    #[test_case]
+    fn now_is_monotonic() {
+        let mut last = time::now();
+        for _ in 0..1000 {
+            let now = time::now();
+            assert!(now >= last);
+            last = now;
+        }
+    }
+
+    #[test_case]
+    fn now_is_finer_than_ticks() {
+        // Without the PIT, two readings within a tick differ
+        if time::clock_source().name() == "pit" {
+            return;
+        }
+        let start = time::now();
+        while time::now() == start {}
+        assert!(time::now().saturating_sub(start) < Duration::from_millis(1));
+        assert!(time::clock_source().resolution() < Duration::from_micros(1));
+    }
+
+    #[test_case]
+    fn now_follows_uptime() {
+        let start_ticks = time::ticks();
+        let (now_before, uptime_before) = (time::now(), time::uptime());
+        while time::ticks() - start_ticks < 50 {
+            hlt();
+        }
+        let (now_after, uptime_after) = (time::now(), time::uptime());
+
+        // Both clocks measure about 50 ms, give or take a tick at both ends
+        let now_elapsed = now_after.saturating_sub(now_before);
+        let uptime_elapsed = uptime_after.saturating_sub(uptime_before);
+        let diff = now_elapsed.abs_diff(uptime_elapsed);
+        assert!(diff <= Duration::from_millis(3));
+    }
+
+    #[test_case]
+    fn hpet_is_present() {
+        // QEMU emulates an HPET on the PC machine type
+        let hpet = time::hpet().expect("no HPET found");
+        assert!(hpet.period_fs() > 0 && hpet.period_fs() <= 100_000_000);
+        let counter = hpet.counter();
+        while hpet.counter() == counter {}
+    }
+
+    #[test_case]
+    fn tsc_frequency_is_plausible() {
+        let tsc = time::tsc().expect("TSC not calibrated");
+        assert!(tsc.frequency() >= 100_000_000 && tsc.frequency() <= 10_000_000_000);
+        assert_eq!(tsc.is_invariant(), tsc::is_invariant());
+        assert!(tsc::rdtsc() > 0);
+    }
+
+    #[test_case]
+    fn pit_clock_matches_uptime() {
+        assert_eq!(PitClock.name(), "pit");
+        assert_eq!(PitClock.resolution(), Duration::from_millis(1));
+        assert!(PitClock.read() <= time::uptime());
+    }
+}
@@ -0,0 +1,66 @@
+//! Integration test for regions backed on demand by the page fault handler
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+
+use bootloader::{BootInfo, entry_point};
+use rust_os::hlt_loop;
+
+entry_point!(main);
+
+/// Panic handler
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+/// Integration test entry point
+//noinspection RsUnresolvedPath
+fn main(boot_info: &'static BootInfo) -> ! {
+    // Initializations
+    rust_os::test_init(boot_info);
+
+    test_main();
+    hlt_loop();
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_os::memory::region::{Backing, RegionKind};
+    use rust_os::memory::{self, region};
+    use x86_64::structures::paging::PageTableFlags;
+
+    #[test_case]
+    fn pages_are_mapped_on_first_access() {
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        let region = region::allocate(
+            "demand",
+            0x4000,
+            0x1000,
+            RegionKind::Other,
+            Backing::OnDemand(flags),
+        )
+        .expect("region allocation failed");
+        let first = region.start;
+        let last = region.start + 0x3000u64;
+        assert!(memory::translate(first).is_err());
+        assert!(memory::translate(last).is_err());
+
+        // The first read maps a zeroed page, and writes stick
+        let ptr: *mut u64 = first.as_mut_ptr();
+        assert_eq!(unsafe { ptr.read_volatile() }, 0);
+        unsafe { ptr.write_volatile(0xdead_beef) };
+        assert_eq!(unsafe { ptr.read_volatile() }, 0xdead_beef);
+
+        let translation = memory::translate(first).expect("page not mapped");
+        assert!(translation.flags.contains(PageTableFlags::WRITABLE));
+
+        // Untouched pages stay unmapped
+        assert!(memory::translate(last).is_err());
+    }
+}
@@ -0,0 +1,97 @@
+//! Integration test for frame allocation
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+
+use bootloader::{BootInfo, entry_point};
+use rust_os::hlt_loop;
+use spin::Once;
+
+entry_point!(main);
+
+/// Boot information passed by the bootloader, used by the test cases
+static BOOT_INFO: Once<&'static BootInfo> = Once::new();
+
+/// Panic handler
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+/// Integration test entry point
+//noinspection RsUnresolvedPath
+fn main(boot_info: &'static BootInfo) -> ! {
+    // Initializations
+    rust_os::init();
+    BOOT_INFO.call_once(|| boot_info);
+
+    test_main();
+    hlt_loop();
+}
+
+/// Return the boot information saved by the entry point
+fn boot_info() -> &'static BootInfo {
+    BOOT_INFO.get().expect("boot info not initialized")
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_os::memory::{self, BootInfoFrameAllocator};
+    use x86_64::VirtAddr;
+    use x86_64::structures::paging::{FrameAllocator, Mapper, Page, PageTableFlags};
+
+    use super::boot_info;
+
+    /// Number of pages to map (about 64 MiB)
+    const PAGE_COUNT: u64 = 16 * 1024;
+
+    /// Virtual address where the test mappings start
+    const MAPPING_START: u64 = 0x0000_5555_0000_0000;
+
+    #[test_case]
+    fn frames_are_distinct_and_ascending() {
+        let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info().memory_map) };
+
+        let mut previous = frame_allocator.allocate_frame().expect("no usable frames");
+        for _ in 0..PAGE_COUNT {
+            let frame = frame_allocator.allocate_frame().expect("out of frames");
+            assert!(frame > previous);
+            previous = frame;
+        }
+    }
+
+    #[test_case]
+    fn map_many_frames() {
+        let phys_mem_offset = VirtAddr::new(boot_info().physical_memory_offset);
+        let mut mapper = unsafe { memory::init(phys_mem_offset) };
+        let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info().memory_map) };
+
+        // Map the pages to fresh frames and tag each of them
+        let start = Page::containing_address(VirtAddr::new(MAPPING_START));
+        for (i, page) in Page::range(start, start + PAGE_COUNT).enumerate() {
+            let frame = frame_allocator.allocate_frame().expect("out of frames");
+            let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+
+            unsafe {
+                mapper
+                    .map_to(page, frame, flags, &mut frame_allocator)
+                    .expect("map_to failed")
+                    .flush();
+                page.start_address()
+                    .as_mut_ptr::<u64>()
+                    .write_volatile(i as u64);
+            }
+        }
+
+        // Check that every page is backed by its own frame
+        for (i, page) in Page::range(start, start + PAGE_COUNT).enumerate() {
+            let value = unsafe { page.start_address().as_ptr::<u64>().read_volatile() };
+            assert_eq!(value, i as u64);
+        }
+    }
+}
@@ -0,0 +1,164 @@
+//! Integration test for frame deallocation
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+
+use bootloader::bootinfo::MemoryRegionType;
+use bootloader::{BootInfo, entry_point};
+use rust_os::memory::bitmap::BitmapFrameAllocator;
+use rust_os::{hlt_loop, memory};
+use spin::{Mutex, Once};
+use x86_64::structures::paging::{OffsetPageTable, PhysFrame};
+use x86_64::{PhysAddr, VirtAddr};
+
+entry_point!(main);
+
+/// Page table mapper shared by the test cases
+static MAPPER: Once<Mutex<OffsetPageTable<'static>>> = Once::new();
+
+/// Frame allocator shared by the test cases
+static FRAME_ALLOCATOR: Once<Mutex<BitmapFrameAllocator>> = Once::new();
+
+/// A frame of the kernel image, which the bootloader reserved
+static KERNEL_FRAME: Once<PhysFrame> = Once::new();
+
+/// Panic handler
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+/// Integration test entry point
+//noinspection RsUnresolvedPath
+fn main(boot_info: &'static BootInfo) -> ! {
+    // Initializations
+    rust_os::init();
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    MAPPER.call_once(|| Mutex::new(unsafe { memory::init(phys_mem_offset) }));
+    FRAME_ALLOCATOR.call_once(|| {
+        let frame_allocator =
+            unsafe { BitmapFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) };
+        Mutex::new(frame_allocator.expect("frame allocator initialization failed"))
+    });
+    KERNEL_FRAME.call_once(|| {
+        let kernel = boot_info
+            .memory_map
+            .iter()
+            .find(|r| r.region_type == MemoryRegionType::Kernel)
+            .expect("no kernel region in the memory map");
+        PhysFrame::containing_address(PhysAddr::new(kernel.range.start_addr()))
+    });
+
+    test_main();
+    hlt_loop();
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_os::memory::FrameError;
+    use x86_64::VirtAddr;
+    use x86_64::structures::paging::{
+        FrameAllocator, FrameDeallocator, Mapper, Page, PageTableFlags,
+    };
+
+    use super::{FRAME_ALLOCATOR, KERNEL_FRAME, MAPPER};
+
+    /// Number of pages to map in each round
+    const PAGE_COUNT: u64 = 1024;
+
+    /// Virtual address where the test mappings start
+    const MAPPING_START: u64 = 0x0000_5555_0000_0000;
+
+    #[test_case]
+    fn allocate_and_free() {
+        let mut frame_allocator = FRAME_ALLOCATOR.get().unwrap().lock();
+        let free = frame_allocator.free_frames();
+
+        let frame = frame_allocator.allocate_frame().expect("out of frames");
+        assert!(frame_allocator.is_used(frame));
+        assert_eq!(frame_allocator.free_frames(), free - 1);
+
+        unsafe { frame_allocator.deallocate_frame(frame) };
+        assert!(!frame_allocator.is_used(frame));
+        assert_eq!(frame_allocator.free_frames(), free);
+        assert_eq!(
+            frame_allocator.free_frames() + frame_allocator.used_frames(),
+            frame_allocator.total_frames()
+        );
+    }
+
+    #[test_case]
+    fn freed_frame_is_reused() {
+        let mut frame_allocator = FRAME_ALLOCATOR.get().unwrap().lock();
+
+        let frame = frame_allocator.allocate_frame().expect("out of frames");
+        unsafe { frame_allocator.deallocate_frame(frame) };
+        assert_eq!(frame_allocator.allocate_frame(), Some(frame));
+        unsafe { frame_allocator.deallocate_frame(frame) };
+    }
+
+    #[test_case]
+    fn double_free() {
+        let mut frame_allocator = FRAME_ALLOCATOR.get().unwrap().lock();
+
+        let frame = frame_allocator.allocate_frame().expect("out of frames");
+        assert_eq!(unsafe { frame_allocator.free_frame(frame) }, Ok(()));
+        assert_eq!(
+            unsafe { frame_allocator.free_frame(frame) },
+            Err(FrameError::DoubleFree(frame))
+        );
+    }
+
+    #[test_case]
+    fn reserved_frame() {
+        let mut frame_allocator = FRAME_ALLOCATOR.get().unwrap().lock();
+        let frame = *KERNEL_FRAME.get().unwrap();
+        let free = frame_allocator.free_frames();
+
+        assert!(frame_allocator.is_used(frame));
+        assert_eq!(
+            unsafe { frame_allocator.free_frame(frame) },
+            Err(FrameError::OutOfRange(frame))
+        );
+        assert!(frame_allocator.is_used(frame));
+        assert_eq!(frame_allocator.free_frames(), free);
+    }
+
+    #[test_case]
+    fn unmap_returns_frames() {
+        let mut mapper = MAPPER.get().unwrap().lock();
+        let mut frame_allocator = FRAME_ALLOCATOR.get().unwrap().lock();
+        let start = Page::containing_address(VirtAddr::new(MAPPING_START));
+        let pages = Page::range(start, start + PAGE_COUNT);
+
+        let mut map_and_unmap = || {
+            for page in pages {
+                let frame = frame_allocator.allocate_frame().expect("out of frames");
+                let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+                unsafe {
+                    mapper
+                        .map_to(page, frame, flags, &mut *frame_allocator)
+                        .expect("map_to failed")
+                        .flush();
+                }
+            }
+
+            for page in pages {
+                let (frame, flush) = mapper.unmap(page).expect("unmap failed");
+                flush.flush();
+                unsafe { frame_allocator.deallocate_frame(frame) };
+            }
+
+            frame_allocator.free_frames()
+        };
+
+        // The first round also allocates the intermediate page tables, which are never freed
+        let free = map_and_unmap();
+        assert_eq!(map_and_unmap(), free);
+    }
+}
@@ -11,9 +11,7 @@ extern crate alloc;
 use core::panic::PanicInfo;
 
 use bootloader::{BootInfo, entry_point};
-use rust_os::memory::BootInfoFrameAllocator;
-use rust_os::{allocator, hlt_loop, memory};
-use x86_64::VirtAddr;
+use rust_os::hlt_loop;
 
 entry_point!(main);
 
@@ -27,11 +25,7 @@ fn panic(info: &PanicInfo) -> ! {
 //noinspection RsUnresolvedPath
 fn main(boot_info: &'static BootInfo) -> ! {
     // Initializations
-    rust_os::init();
-    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
-    let mut mapper = unsafe { memory::init(phys_mem_offset) };
-    let mut frame_allocator = unsafe { BootInfoFrameAllocator::init(&boot_info.memory_map) };
-    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    rust_os::test_init(boot_info);
 
     test_main();
     hlt_loop();
@@ -40,9 +34,13 @@ fn main(boot_info: &'static BootInfo) -> ! {
 #[cfg(test)]
 mod tests {
     use alloc::boxed::Box;
+    use alloc::vec;
     use alloc::vec::Vec;
 
-    use rust_os::allocator::HEAP_SIZE;
+    use rust_os::allocator::{self, HEAP_MAX_SIZE, HEAP_SIZE};
+    use rust_os::memory;
+    use x86_64::VirtAddr;
+    use x86_64::structures::paging::{PageSize, Size2MiB, Size4KiB};
 
     #[test_case]
     fn simple_allocation() {
@@ -69,4 +67,91 @@ mod tests {
             assert_eq!(*x, i);
         }
     }
+
+    // The bump allocator can't reuse memory while a long-lived allocation exists
+    #[cfg(not(feature = "bump_allocator"))]
+    #[test_case]
+    fn many_boxes_long_lived() {
+        let long_lived = Box::new(1);
+        for i in 0..HEAP_SIZE {
+            let x = Box::new(i);
+            assert_eq!(*x, i);
+        }
+        assert_eq!(*long_lived, 1);
+    }
+
+    #[test_case]
+    fn fragmentation() {
+        // Allocate blocks of different sizes, too large for any fixed-size block list
+        let mut blocks: Vec<Option<Vec<u8>>> = (0..16u8)
+            .map(|i| Some(vec![i; 4096 + usize::from(i) * 64]))
+            .collect();
+
+        // Free every other block to leave holes in the heap, then check the surviving blocks
+        for block in blocks.iter_mut().step_by(2) {
+            *block = None;
+        }
+        for (i, block) in (0..16u8).zip(&blocks) {
+            if let Some(block) = block {
+                assert!(block.iter().all(|&b| b == i));
+            }
+        }
+
+        // Free the remaining blocks in reverse order
+        while let Some(block) = blocks.pop() {
+            drop(block);
+        }
+        drop(blocks);
+
+        // This only succeeds if all the holes have been merged back together
+        let large = vec![0xaau8; HEAP_SIZE * 3 / 4];
+        assert!(large.iter().all(|&b| b == 0xaa));
+    }
+
+    #[test_case]
+    fn heap_grows() {
+        let n = 4 * HEAP_SIZE;
+        let vec = vec![0x55u8; n];
+        assert!(allocator::heap_size() > HEAP_SIZE);
+        assert!(vec.iter().all(|&b| b == 0x55));
+    }
+
+    #[test_case]
+    fn heap_grows_with_huge_pages() {
+        let vec = vec![0x66u8; usize::try_from(4 * Size2MiB::SIZE).unwrap()];
+        assert!(vec.iter().all(|&b| b == 0x66));
+
+        // The initial heap uses 4 KiB pages, and the growth past the first 2 MiB 2 MiB pages
+        let start = VirtAddr::new(allocator::heap_start() as u64);
+        let boundary = start + Size2MiB::SIZE;
+        assert!(boundary + Size2MiB::SIZE <= start + allocator::heap_size() as u64);
+        let translation = memory::translate(start).expect("heap not mapped");
+        assert_eq!(translation.page_size, Size4KiB::SIZE);
+        let translation = memory::translate(boundary).expect("heap not mapped");
+        assert_eq!(translation.page_size, Size2MiB::SIZE);
+    }
+
+    #[test_case]
+    fn heap_limit() {
+        let mut vec = Vec::<u8>::new();
+        assert!(vec.try_reserve(HEAP_MAX_SIZE).is_err());
+        assert!(allocator::heap_size() <= HEAP_MAX_SIZE);
+    }
+
+    #[test_case]
+    fn stats() {
+        let before = allocator::stats();
+        assert!(before.largest_free_block <= before.bytes_free);
+
+        let vec = vec![0u8; 1024];
+        let during = allocator::stats();
+        assert_eq!(during.bytes_allocated, before.bytes_allocated + vec.len());
+        assert!(during.peak_bytes_allocated >= during.bytes_allocated);
+        assert_eq!(during.allocations, before.allocations + 1);
+
+        drop(vec);
+        let after = allocator::stats();
+        assert_eq!(after.deallocations, before.deallocations + 1);
+        allocator::assert_no_leaks(&before);
+    }
 }
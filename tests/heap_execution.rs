@@ -0,0 +1,111 @@
+//! Integration test for no-execute heap pages and W^X kernel mappings
+
+#![no_std]
+#![no_main]
+#![feature(abi_x86_interrupt)]
+
+extern crate alloc;
+
+use alloc::boxed::Box;
+use core::panic::PanicInfo;
+
+use bootloader::{BootInfo, entry_point};
+use lazy_static::lazy_static;
+use rust_os::memory::buddy::BuddyFrameAllocator;
+use rust_os::memory::{protect, walk};
+use rust_os::{QemuExitCode, allocator, exit_qemu, hlt_loop, memory, serial_print, serial_println};
+use x86_64::VirtAddr;
+use x86_64::registers::control::Cr2;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use x86_64::structures::paging::PageTableFlags;
+
+lazy_static! {
+    /// IDT for testing
+    static ref TEST_IDT: InterruptDescriptorTable = {
+        // Create the IDT
+        let mut idt = InterruptDescriptorTable::new();
+
+        // Set the page fault handler function
+        idt.page_fault.set_handler_fn(test_page_fault_handler);
+
+        idt
+    };
+}
+
+/// Load the test IDT in the CPU
+pub fn init_test_idt() {
+    TEST_IDT.load();
+}
+
+/// Address of the heap code, checked by the page fault handler
+static mut HEAP_CODE: u64 = 0;
+
+entry_point!(main);
+
+/// Panic handler
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+/// Integration test entry point
+#[allow(clippy::missing_panics_doc)]
+fn main(boot_info: &'static BootInfo) -> ! {
+    serial_print!("heap_execution::heap_execution... ");
+
+    // Initialize the OS with a custom IDT
+    rust_os::gdt::init();
+    init_test_idt();
+    protect::init();
+    assert!(protect::nx_enabled(), "no-execute pages not enabled");
+
+    // Initialize the heap and enforce W^X
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mut mapper = unsafe { memory::init(phys_mem_offset) };
+    let mut frame_allocator =
+        unsafe { BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) }
+            .expect("frame allocator initialization failed");
+    allocator::init_heap(&mut mapper, &mut frame_allocator).expect("heap initialization failed");
+    memory::init_kernel_memory(mapper, frame_allocator);
+    protect::enforce_wx().expect("W^X enforcement failed");
+
+    // No mapping is both writable and executable, and the kernel text is read-only
+    walk::for_each_mapping(|mapping| {
+        assert!(
+            !mapping.flags.contains(PageTableFlags::WRITABLE)
+                || mapping.flags.contains(PageTableFlags::NO_EXECUTE),
+            "writable and executable mapping: {mapping}"
+        );
+    });
+    let text = walk::find_mapping(VirtAddr::from_ptr(main as *const ())).expect("text not mapped");
+    assert!(!text.flags.contains(PageTableFlags::WRITABLE));
+    assert!(!text.flags.contains(PageTableFlags::NO_EXECUTE));
+
+    // Jump to a `ret` instruction on the heap
+    let code = Box::new([0xc3u8; 16]);
+    let code_ptr = Box::into_raw(code);
+    unsafe {
+        HEAP_CODE = code_ptr as u64;
+        let f: extern "C" fn() = core::mem::transmute(code_ptr);
+        f();
+    }
+
+    panic!("Execution continued after jumping to the heap");
+}
+
+extern "x86-interrupt" fn test_page_fault_handler(
+    _stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    // The fault must be an instruction fetch from the heap code
+    let heap_code = unsafe { HEAP_CODE };
+    let addr = Cr2::read_raw();
+    if !error_code.contains(PageFaultErrorCode::INSTRUCTION_FETCH) || addr != heap_code {
+        serial_println!("[unexpected page fault: {:?} at {:#x}]", error_code, addr);
+        exit_qemu(QemuExitCode::Failure);
+    }
+
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    hlt_loop();
+}
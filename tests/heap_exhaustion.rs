@@ -0,0 +1,85 @@
+//! Integration test for heap exhaustion
+
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+
+use bootloader::{BootInfo, entry_point};
+use rust_os::allocator::HEAP_MAX_SIZE;
+use rust_os::{QemuExitCode, exit_qemu, hlt_loop, serial_print, serial_println};
+
+entry_point!(main);
+
+/// Fixed-size buffer to format panic messages into, since the heap is exhausted
+struct Buffer {
+    bytes: [u8; 128],
+    len: usize,
+}
+
+impl Buffer {
+    /// Format the passed arguments into a new buffer, truncating them if they don't fit
+    fn format(args: fmt::Arguments) -> Self {
+        let mut buffer = Self {
+            bytes: [0; 128],
+            len: 0,
+        };
+        let _ = buffer.write_fmt(args);
+        buffer
+    }
+
+    /// Return the formatted bytes
+    fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+}
+
+impl Write for Buffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let end = self.len + s.len();
+        self.bytes
+            .get_mut(self.len..end)
+            .ok_or(fmt::Error)?
+            .copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
+/// Panic handler, which passes the test only if the panic comes from the allocation error handler
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    let message = Buffer::format(format_args!("{}", info.message()));
+    let expected = Buffer::format(format_args!(
+        "heap exhausted: allocation of {HEAP_MAX_SIZE} bytes failed"
+    ));
+    if message.as_bytes() != expected.as_bytes() {
+        rust_os::test_panic_handler(info);
+    }
+
+    serial_println!("[ok]");
+    exit_qemu(QemuExitCode::Success);
+    hlt_loop();
+}
+
+/// Integration test entry point
+//noinspection RsUnresolvedPath
+fn main(boot_info: &'static BootInfo) -> ! {
+    // Initializations
+    rust_os::test_init(boot_info);
+
+    exhaust_heap();
+    serial_println!("[test did not panic]");
+    exit_qemu(QemuExitCode::Failure);
+    hlt_loop();
+}
+
+fn exhaust_heap() {
+    serial_print!("heap_exhaustion::exhaust_heap... ");
+    let vec = Vec::<u8>::with_capacity(HEAP_MAX_SIZE);
+    assert!(vec.capacity() >= HEAP_MAX_SIZE);
+}
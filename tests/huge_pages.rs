@@ -0,0 +1,156 @@
+//! Integration test for huge page mappings
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+
+use bootloader::{BootInfo, entry_point};
+use rust_os::hlt_loop;
+
+entry_point!(main);
+
+/// Panic handler
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+/// Integration test entry point
+//noinspection RsUnresolvedPath
+fn main(boot_info: &'static BootInfo) -> ! {
+    // Initializations
+    rust_os::test_init(boot_info);
+
+    test_main();
+    hlt_loop();
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_os::memory::huge::{self, HugePageError};
+    use rust_os::memory::{self, TranslateError, walk};
+    use x86_64::VirtAddr;
+    use x86_64::instructions::tlb;
+    use x86_64::registers::control::Cr3;
+    use x86_64::structures::paging::mapper::MapToError;
+    use x86_64::structures::paging::page_table::{PageTableEntry, PageTableLevel};
+    use x86_64::structures::paging::{
+        Page, PageSize, PageTable, PageTableFlags, Size1GiB, Size2MiB,
+    };
+
+    /// Virtual address where the test mappings start, aligned to 1 GiB
+    const MAPPING_START: u64 = 0x0000_7777_0000_0000;
+
+    /// PAT bit of a huge page entry, in the lowest bit of its address field
+    const HUGE_PAGE_PAT: u64 = 1 << 12;
+
+    /// Return the level 2 entry that maps the passed 2 MiB page
+    fn level_2_entry(page: Page<Size2MiB>) -> &'static mut PageTableEntry {
+        let offset = memory::physical_memory_offset().expect("memory not initialized");
+        let mut table_addr = Cr3::read().0.start_address();
+        let addr = page.start_address();
+        for level in [PageTableLevel::Four, PageTableLevel::Three] {
+            let table: &PageTable = unsafe { &*(offset + table_addr.as_u64()).as_ptr() };
+            table_addr = table[addr.page_table_index(level)].addr();
+        }
+        let table: &mut PageTable = unsafe { &mut *(offset + table_addr.as_u64()).as_mut_ptr() };
+        &mut table[addr.page_table_index(PageTableLevel::Two)]
+    }
+
+    /// Return the number of free frames in the kernel frame allocator
+    fn free_frames() -> usize {
+        memory::with_kernel_memory(|memory| memory.frame_allocator.free_frames())
+            .expect("kernel memory not initialized")
+    }
+
+    #[test_case]
+    fn map_2mib_pages() {
+        let start = Page::<Size2MiB>::containing_address(VirtAddr::new(MAPPING_START));
+        let pages = Page::range(start, start + 2);
+        let free = free_frames();
+
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        huge::map_kernel_pages(pages, flags).expect("mapping failed");
+
+        // Each page is a single 2 MiB translation backed by aligned contiguous memory
+        for page in pages {
+            let translation = memory::translate(page.start_address()).expect("not mapped");
+            assert_eq!(translation.page_size, Size2MiB::SIZE);
+            assert!(translation.phys_addr.is_aligned(Size2MiB::SIZE));
+        }
+
+        let len = usize::try_from(2 * Size2MiB::SIZE).unwrap();
+        let memory = unsafe {
+            core::slice::from_raw_parts_mut(start.start_address().as_mut_ptr::<u64>(), len / 8)
+        };
+        memory.fill(0x0123_4567_89ab_cdef);
+        assert!(memory.iter().all(|&x| x == 0x0123_4567_89ab_cdef));
+
+        unsafe { huge::unmap_kernel_pages(pages) }.expect("unmapping failed");
+        assert_eq!(
+            memory::translate(start.start_address()),
+            Err(TranslateError::NotPresent(PageTableLevel::Two))
+        );
+
+        // Only the page tables created for the mapping are not given back
+        assert!(free - free_frames() <= 2);
+    }
+
+    #[test_case]
+    fn translate_pat_2mib_page() {
+        let page = Page::<Size2MiB>::containing_address(VirtAddr::new(MAPPING_START));
+        let pages = Page::range(page, page + 1);
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+        huge::map_kernel_pages(pages, flags).expect("mapping failed");
+        let phys = memory::translate(page.start_address())
+            .expect("not mapped")
+            .phys_addr;
+
+        // Set the PAT bit, which selects PAT entry 4 (write-back, unless reprogrammed)
+        let entry = level_2_entry(page);
+        let entry_flags = entry.flags();
+        entry.set_addr(phys + HUGE_PAGE_PAT, entry_flags);
+        tlb::flush(page.start_address());
+
+        let addr = page.start_address() + 0x1234u64;
+        let translation = memory::translate(addr).expect("not mapped");
+        assert_eq!(translation.phys_addr, phys + 0x1234u64);
+        assert_eq!(translation.page_size, Size2MiB::SIZE);
+        assert!(translation.flags.contains(walk::PAT));
+        let mapping = walk::find_mapping(addr).expect("not mapped");
+        assert_eq!(mapping.translate(addr), Some(translation.phys_addr));
+        let accessed = PageTableFlags::ACCESSED | PageTableFlags::DIRTY;
+        assert_eq!(mapping.flags, translation.flags - accessed);
+
+        // Clear the PAT bit, so that the mapper sees an aligned frame again
+        entry.set_addr(phys, entry_flags);
+        tlb::flush(page.start_address());
+        let translation = memory::translate(addr).expect("not mapped");
+        assert!(!translation.flags.contains(walk::PAT));
+        unsafe { huge::unmap_kernel_pages(pages) }.expect("unmapping failed");
+    }
+
+    #[test_case]
+    fn map_1gib_page() {
+        let page =
+            Page::<Size1GiB>::containing_address(VirtAddr::new(MAPPING_START + Size1GiB::SIZE));
+        let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+
+        match huge::map_kernel_pages(Page::range(page, page + 1), flags) {
+            Ok(()) => {
+                let translation = memory::translate(page.start_address()).expect("not mapped");
+                assert_eq!(translation.page_size, Size1GiB::SIZE);
+                unsafe { huge::unmap_kernel_pages(Page::range(page, page + 1)) }
+                    .expect("unmapping failed");
+            }
+            // The test machine may not support 1 GiB pages or have that much contiguous memory
+            Err(HugePageError::Unsupported) => assert!(!huge::supports_1gib_pages()),
+            Err(HugePageError::Map(MapToError::FrameAllocationFailed)) => {}
+            Err(err) => panic!("unexpected error: {err:?}"),
+        }
+    }
+}
@@ -0,0 +1,139 @@
+//! Integration test for the KASAN-lite debugging allocator
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+
+use bootloader::{BootInfo, entry_point};
+use rust_os::hlt_loop;
+
+entry_point!(main);
+
+/// Panic handler
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+/// Integration test entry point
+//noinspection RsUnresolvedPath
+fn main(boot_info: &'static BootInfo) -> ! {
+    // Initializations
+    rust_os::test_init(boot_info);
+
+    test_main();
+    hlt_loop();
+}
+
+#[cfg(test)]
+mod tests {
+    use core::alloc::Layout;
+
+    use rust_os::allocator::HeapAllocator;
+    use rust_os::allocator::kasan::{Kasan, POISON_BYTE, QUARANTINE_LEN, REDZONE_BYTE};
+    use rust_os::allocator::linked_list::LinkedListAllocator;
+
+    /// Size of the test heap in bytes
+    const ARENA_SIZE: usize = 64 * 1024;
+
+    /// Memory for the test heap
+    #[repr(align(4096))]
+    struct Arena([u8; ARENA_SIZE]);
+
+    /// Test heap, reused by each test case
+    static mut ARENA: Arena = Arena([0; ARENA_SIZE]);
+
+    /// Return a new [`Kasan`] allocator that manages the test heap
+    fn kasan() -> Kasan<LinkedListAllocator> {
+        let mut kasan = Kasan::new(LinkedListAllocator::new());
+        unsafe { kasan.init((&raw mut ARENA.0).addr(), ARENA_SIZE) };
+        kasan
+    }
+
+    #[test_case]
+    fn clean_allocations() {
+        let mut kasan = kasan();
+        let layout = Layout::from_size_align(24, 8).unwrap();
+        let ptr = kasan.allocate(layout);
+        assert!(!ptr.is_null());
+        unsafe {
+            assert_eq!(ptr.sub(1).read(), REDZONE_BYTE);
+            assert_eq!(ptr.add(layout.size()).read(), REDZONE_BYTE);
+            ptr.write_bytes(0xaa, layout.size());
+            kasan.deallocate(ptr, layout);
+
+            // Freed memory is poisoned
+            assert_eq!(ptr.read(), POISON_BYTE);
+        }
+
+        kasan.flush_quarantine();
+        assert_eq!(kasan.reports(), 0);
+    }
+
+    #[test_case]
+    fn overflow_and_underflow() {
+        let mut kasan = kasan();
+        let layout = Layout::from_size_align(10, 2).unwrap();
+        let ptr = kasan.allocate(layout);
+        unsafe {
+            ptr.add(layout.size()).write(0);
+            kasan.deallocate(ptr, layout);
+        }
+        assert_eq!(kasan.reports(), 1);
+
+        let ptr = kasan.allocate(layout);
+        unsafe {
+            ptr.sub(1).write(0);
+            kasan.deallocate(ptr, layout);
+        }
+        assert_eq!(kasan.reports(), 2);
+    }
+
+    #[test_case]
+    fn write_after_free() {
+        let mut kasan = kasan();
+        let layout = Layout::new::<u64>();
+        let ptr = kasan.allocate(layout);
+        unsafe {
+            kasan.deallocate(ptr, layout);
+            ptr.write(0);
+        }
+        assert_eq!(kasan.reports(), 0);
+
+        // The write is detected when the block leaves the quarantine
+        for _ in 0..QUARANTINE_LEN {
+            let other = kasan.allocate(layout);
+            unsafe { kasan.deallocate(other, layout) };
+        }
+        assert_eq!(kasan.reports(), 1);
+    }
+
+    #[test_case]
+    fn double_free() {
+        let mut kasan = kasan();
+        let layout = Layout::new::<u32>();
+        let ptr = kasan.allocate(layout);
+        unsafe {
+            kasan.deallocate(ptr, layout);
+            kasan.deallocate(ptr, layout);
+        }
+        assert_eq!(kasan.reports(), 1);
+    }
+
+    #[test_case]
+    fn quarantine_delays_reuse() {
+        let mut kasan = kasan();
+        let layout = Layout::new::<u64>();
+        let ptr = kasan.allocate(layout);
+        unsafe { kasan.deallocate(ptr, layout) };
+        assert_ne!(kasan.allocate(layout), ptr);
+
+        // The quarantine is flushed instead of failing large allocations
+        let large = Layout::from_size_align(ARENA_SIZE / 2, 8).unwrap();
+        assert!(!kasan.allocate(large).is_null());
+    }
+}
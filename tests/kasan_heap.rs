@@ -0,0 +1,100 @@
+//! Integration test for the KASAN-lite debugging allocator wrapping the global allocator (requires
+//! the `kasan` feature, e.g. `cargo test --features kasan --test kasan_heap`)
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use core::panic::PanicInfo;
+
+use bootloader::{BootInfo, entry_point};
+use rust_os::hlt_loop;
+
+entry_point!(main);
+
+/// Panic handler
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+/// Integration test entry point
+//noinspection RsUnresolvedPath
+fn main(boot_info: &'static BootInfo) -> ! {
+    // Initializations
+    rust_os::test_init(boot_info);
+
+    test_main();
+    hlt_loop();
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::boxed::Box;
+    use alloc::string::String;
+    use alloc::vec::Vec;
+    use core::alloc::Layout;
+
+    use rust_os::allocator;
+
+    #[test_case]
+    fn clean_allocations() {
+        let reports = allocator::kasan_reports();
+
+        let mut vec = Vec::new();
+        for i in 0..100u64 {
+            vec.push(Box::new(i));
+        }
+        let mut string = String::new();
+        for _ in 0..10 {
+            string.push_str("kasan");
+        }
+        assert_eq!(string.len(), 50);
+        drop(vec);
+        drop(string);
+
+        assert_eq!(allocator::kasan_reports(), reports);
+    }
+
+    #[test_case]
+    fn vec_overflow() {
+        let reports = allocator::kasan_reports();
+
+        // Write one byte past the end of the vector, into the redzone that follows it
+        let mut vec = Vec::<u8>::with_capacity(24);
+        let ptr = vec.as_mut_ptr();
+        let past_end = ptr.wrapping_add(vec.capacity());
+        unsafe { past_end.write_volatile(0) };
+        drop(vec);
+
+        assert_eq!(allocator::kasan_reports(), reports + 1);
+        let report = allocator::kasan_last_report().expect("no KASAN report");
+        assert_eq!(report.error, "heap buffer overflow");
+        assert_eq!(report.addr, past_end.cast_const());
+        assert_eq!(report.ptr, ptr.cast_const());
+        assert_eq!(report.layout, Layout::array::<u8>(24).unwrap());
+    }
+
+    #[test_case]
+    fn box_underflow() {
+        let reports = allocator::kasan_reports();
+
+        // Write one byte before the start of the box, into the redzone that precedes it
+        let boxed = Box::new([0u64; 4]);
+        let ptr = Box::into_raw(boxed);
+        let before = ptr.cast::<u8>().wrapping_sub(1);
+        unsafe {
+            before.write_volatile(0);
+            drop(Box::from_raw(ptr));
+        }
+
+        assert_eq!(allocator::kasan_reports(), reports + 1);
+        let report = allocator::kasan_last_report().expect("no KASAN report");
+        assert_eq!(report.error, "heap buffer underflow");
+        assert_eq!(report.addr, before.cast_const());
+    }
+}
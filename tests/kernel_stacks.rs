@@ -0,0 +1,160 @@
+//! Integration test for guarded kernel stacks
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+
+use bootloader::{BootInfo, entry_point};
+use rust_os::hlt_loop;
+
+entry_point!(main);
+
+/// Panic handler
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+/// Integration test entry point
+//noinspection RsUnresolvedPath
+fn main(boot_info: &'static BootInfo) -> ! {
+    // Initializations
+    rust_os::test_init(boot_info);
+
+    test_main();
+    hlt_loop();
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_os::memory::stack::{self, STACK_REGION_SIZE, StackError};
+    use rust_os::{gdt, memory};
+    use x86_64::structures::paging::mapper::MapToError;
+    use x86_64::structures::paging::{
+        FrameAllocator, FrameDeallocator, Mapper, Page, PageTableFlags, Translate,
+    };
+
+    /// Number of pages of each test stack
+    const STACK_PAGES: u64 = 4;
+
+    #[test_case]
+    fn stack_is_usable() {
+        let stack = stack::alloc_stack(STACK_PAGES).expect("stack allocation failed");
+        assert_eq!(stack.end() - stack.start(), STACK_PAGES * 4096);
+        let region = stack::stack_region().expect("stack region not allocated");
+        assert!(stack.start() >= region.start);
+        assert!(stack.end().as_u64() <= region.end());
+
+        let stack = unsafe {
+            core::slice::from_raw_parts_mut(
+                stack.start().as_mut_ptr::<u8>(),
+                usize::try_from(stack.end() - stack.start()).unwrap(),
+            )
+        };
+        stack.fill(0x55);
+        assert!(stack.iter().all(|&b| b == 0x55));
+    }
+
+    #[test_case]
+    fn guard_page_is_unmapped() {
+        let stack = stack::alloc_stack(STACK_PAGES).expect("stack allocation failed");
+
+        memory::with_kernel_memory(|memory| {
+            let guard = stack.guard_page().start_address();
+            assert!(memory.mapper.translate_addr(guard).is_none());
+            assert!(memory.mapper.translate_addr(stack.start()).is_some());
+        })
+        .expect("kernel memory not initialized");
+    }
+
+    #[test_case]
+    fn stacks_do_not_overlap() {
+        let stack_1 = stack::alloc_stack(STACK_PAGES).expect("stack allocation failed");
+        let stack_2 = stack::alloc_stack(STACK_PAGES).expect("stack allocation failed");
+        assert!(stack_2.guard_page().start_address() >= stack_1.end());
+    }
+
+    /// Return the number of free frames in the kernel frame allocator
+    fn free_frames() -> usize {
+        memory::with_kernel_memory(|memory| memory.frame_allocator.free_frames())
+            .expect("kernel memory not initialized")
+    }
+
+    #[test_case]
+    fn free_stack() {
+        let stack = stack::alloc_stack(STACK_PAGES).expect("stack allocation failed");
+        let free = free_frames();
+
+        unsafe { stack::free_stack(stack) }.expect("kernel memory not initialized");
+        assert_eq!(free_frames(), free + usize::try_from(STACK_PAGES).unwrap());
+        memory::with_kernel_memory(|memory| {
+            assert!(memory.mapper.translate_addr(stack.start()).is_none());
+        })
+        .expect("kernel memory not initialized");
+
+        // The last stack's room is reclaimed
+        let again = stack::alloc_stack(STACK_PAGES).expect("stack allocation failed");
+        assert_eq!(again, stack);
+    }
+
+    #[test_case]
+    fn failed_allocation_is_rolled_back() {
+        // Find where the next stack goes, and map one of its pages beforehand
+        let stack = stack::alloc_stack(STACK_PAGES).expect("stack allocation failed");
+        unsafe { stack::free_stack(stack) }.expect("kernel memory not initialized");
+        let blocker: Page = Page::containing_address(stack.start()) + 2;
+        memory::with_kernel_memory(|memory| {
+            let frame = memory
+                .frame_allocator
+                .allocate_frame()
+                .expect("out of frames");
+            let flags = PageTableFlags::PRESENT | PageTableFlags::WRITABLE;
+            unsafe {
+                memory
+                    .mapper
+                    .map_to(blocker, frame, flags, &mut memory.frame_allocator)
+                    .expect("map_to failed")
+                    .flush();
+            }
+        })
+        .expect("kernel memory not initialized");
+
+        let free = free_frames();
+        assert!(matches!(
+            stack::alloc_stack(STACK_PAGES),
+            Err(StackError::Map(MapToError::PageAlreadyMapped(_)))
+        ));
+        assert_eq!(free_frames(), free);
+
+        // The pages mapped before the failure are unmapped, and the room is reclaimed
+        memory::with_kernel_memory(|memory| {
+            assert!(memory.mapper.translate_addr(stack.start()).is_none());
+            let (frame, flush) = memory.mapper.unmap(blocker).expect("unmap failed");
+            flush.flush();
+            unsafe { memory.frame_allocator.deallocate_frame(frame) };
+        })
+        .expect("kernel memory not initialized");
+        let again = stack::alloc_stack(STACK_PAGES).expect("stack allocation failed");
+        assert_eq!(again, stack);
+    }
+
+    #[test_case]
+    fn region_exhausted() {
+        assert!(matches!(
+            stack::alloc_stack(STACK_REGION_SIZE / 4096),
+            Err(StackError::RegionExhausted)
+        ));
+    }
+
+    #[test_case]
+    fn interrupt_stacks() {
+        gdt::init_interrupt_stacks().expect("interrupt stack allocation failed");
+
+        // Interrupts are handled on the new stacks
+        x86_64::instructions::interrupts::int3();
+    }
+}
@@ -0,0 +1,136 @@
+//! Integration test for kernel virtual memory regions
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+
+use bootloader::{BootInfo, entry_point};
+use rust_os::hlt_loop;
+
+entry_point!(main);
+
+/// Panic handler
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+/// Integration test entry point
+//noinspection RsUnresolvedPath
+fn main(boot_info: &'static BootInfo) -> ! {
+    // Initializations
+    rust_os::test_init(boot_info);
+
+    test_main();
+    hlt_loop();
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_os::allocator::{self, HEAP_MAX_SIZE};
+    use rust_os::memory::region::{
+        self, Backing, DYNAMIC_END, DYNAMIC_START, RegionError, RegionKind,
+    };
+    use rust_os::memory::stack::{self, STACK_REGION_SIZE};
+    use x86_64::VirtAddr;
+
+    #[test_case]
+    fn heap_is_reserved() {
+        let heap_start = VirtAddr::new(allocator::heap_start() as u64);
+        let heap = region::find(heap_start).expect("heap region not reserved");
+        assert_eq!(heap.kind, RegionKind::Heap);
+        assert_eq!(heap.start, heap_start);
+        assert_eq!(heap.size, HEAP_MAX_SIZE as u64);
+        assert!(heap.start.as_u64() >= DYNAMIC_START && heap.end() <= DYNAMIC_END);
+        assert!(heap.start.is_aligned(0x20_0000u64));
+
+        // Nothing else can be placed in the heap range
+        assert_eq!(
+            region::reserve(
+                "overlap",
+                heap_start + 0x1000u64,
+                0x1000,
+                RegionKind::Other,
+                Backing::Eager
+            ),
+            Err(RegionError::Overlap(heap))
+        );
+    }
+
+    #[test_case]
+    fn stacks_are_reserved() {
+        let stack = stack::alloc_stack(1).expect("stack allocation failed");
+        let stacks = stack::stack_region().expect("stack region not allocated");
+        assert_eq!(region::find(stack.start()), Some(stacks));
+        assert_eq!(stacks.kind, RegionKind::Stack);
+        assert_eq!(stacks.size, STACK_REGION_SIZE);
+        assert!(stacks.start.as_u64() >= DYNAMIC_START && stacks.end() <= DYNAMIC_END);
+    }
+
+    #[test_case]
+    fn allocate_and_release() {
+        let region_1 =
+            region::allocate("test 1", 0x3000, 0x1000, RegionKind::Other, Backing::Eager)
+                .expect("region allocation failed");
+        let region_2 = region::allocate(
+            "test 2",
+            0x20_0000,
+            0x20_0000,
+            RegionKind::Mmio,
+            Backing::Eager,
+        )
+        .expect("region allocation failed");
+        for region in [region_1, region_2] {
+            assert!(region.start.as_u64() >= DYNAMIC_START && region.end() <= DYNAMIC_END);
+        }
+        assert!(region_2.start.is_aligned(0x20_0000u64));
+        assert!(!region_1.overlaps(&region_2));
+
+        // A released range is handed out again
+        assert_eq!(region::release(region_1.start), Ok(region_1));
+        assert_eq!(
+            region::release(region_1.start),
+            Err(RegionError::NotFound(region_1.start))
+        );
+        let region_3 =
+            region::allocate("test 3", 0x1000, 0x1000, RegionKind::Other, Backing::Eager)
+                .expect("region allocation failed");
+        assert_eq!(region_3.start, region_1.start);
+
+        region::release(region_2.start).expect("release failed");
+        region::release(region_3.start).expect("release failed");
+    }
+
+    #[test_case]
+    fn invalid_ranges() {
+        let start = VirtAddr::new(DYNAMIC_START);
+        assert_eq!(
+            region::reserve("empty", start, 0, RegionKind::Other, Backing::Eager),
+            Err(RegionError::InvalidRange)
+        );
+        assert_eq!(
+            region::reserve(
+                "misaligned",
+                start + 1u64,
+                0x1000,
+                RegionKind::Other,
+                Backing::Eager
+            ),
+            Err(RegionError::InvalidRange)
+        );
+        assert_eq!(
+            region::allocate(
+                "too large",
+                DYNAMIC_END,
+                0x1000,
+                RegionKind::Other,
+                Backing::Eager
+            ),
+            Err(RegionError::OutOfSpace)
+        );
+    }
+}
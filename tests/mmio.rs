@@ -0,0 +1,108 @@
+//! Integration test for memory-mapped I/O mappings
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+
+use bootloader::{BootInfo, entry_point};
+use rust_os::hlt_loop;
+
+entry_point!(main);
+
+/// Panic handler
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+/// Integration test entry point
+//noinspection RsUnresolvedPath
+fn main(boot_info: &'static BootInfo) -> ! {
+    // Initializations
+    rust_os::test_init(boot_info);
+
+    test_main();
+    hlt_loop();
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_os::memory::mmio::{self, CacheMode};
+    use rust_os::memory::{self, region, walk};
+    use x86_64::PhysAddr;
+    use x86_64::registers::model_specific::Msr;
+    use x86_64::structures::paging::PageTableFlags;
+
+    /// Physical address of the VGA text buffer
+    const VGA_BUFFER: u64 = 0xb8000;
+
+    #[test_case]
+    fn map_uncached() {
+        let phys = PhysAddr::new(VGA_BUFFER + 0x10);
+        let mmio = mmio::map_mmio(phys, 0x20).expect("MMIO mapping failed");
+        assert_eq!(mmio.virt_addr().as_u64() & 0xfff, 0x10);
+        assert_eq!(mmio.len(), 0x20);
+
+        let translation = memory::translate(mmio.virt_addr()).expect("range not mapped");
+        assert_eq!(translation.phys_addr, phys);
+        assert!(
+            translation
+                .flags
+                .contains(PageTableFlags::NO_CACHE | PageTableFlags::WRITE_THROUGH)
+        );
+        let mapping = walk::find_mapping(mmio.virt_addr()).expect("range not mapped");
+        assert!(!mapping.flags.contains(walk::PAT));
+
+        // Writes through the handle reach the physical memory
+        let offset = memory::physical_memory_offset().expect("memory not initialized");
+        let alias: *const u16 = (offset + phys.as_u64()).as_ptr();
+        let old = mmio.read::<u16>(0);
+        mmio.write::<u16>(0, 0x0f41);
+        assert_eq!(unsafe { alias.read_volatile() }, 0x0f41);
+        mmio.write(0, old);
+
+        // Dropping the handle unmaps the range and releases its region
+        let virt = mmio.virt_addr();
+        drop(mmio);
+        assert!(memory::translate(virt).is_err());
+        assert!(region::find(virt).is_none());
+    }
+
+    #[test_case]
+    fn map_write_combining() {
+        if !mmio::supports_pat() {
+            return;
+        }
+
+        let phys = PhysAddr::new(VGA_BUFFER);
+        let mmio = mmio::map_mmio_with(phys, 0x2000, CacheMode::WriteCombining)
+            .expect("MMIO mapping failed");
+        assert_eq!(mmio.mode(), CacheMode::WriteCombining);
+
+        // The PAT bit of level 1 entries has the same position as HUGE_PAGE, and selects PAT entry 4
+        let translation =
+            memory::translate(mmio.virt_addr() + 0x1000u64).expect("range not mapped");
+        assert_eq!(translation.phys_addr, phys + 0x1000u64);
+        assert!(translation.flags.contains(PageTableFlags::HUGE_PAGE));
+        let mapping = walk::find_mapping(mmio.virt_addr()).expect("range not mapped");
+        assert!(mapping.flags.contains(walk::PAT));
+        let pat = unsafe { Msr::new(0x277).read() };
+        assert_eq!((pat >> 32) & 0xff, 0x01);
+
+        let virt = mmio.virt_addr();
+        drop(mmio);
+        assert!(memory::translate(virt).is_err());
+    }
+
+    #[test_case]
+    fn invalid_ranges() {
+        assert!(matches!(
+            mmio::map_mmio(PhysAddr::new(VGA_BUFFER), 0),
+            Err(mmio::MmioError::InvalidRange)
+        ));
+    }
+}
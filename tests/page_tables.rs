@@ -0,0 +1,138 @@
+//! Integration test for page table inspection
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+
+use bootloader::{BootInfo, entry_point};
+use rust_os::hlt_loop;
+
+entry_point!(main);
+
+/// Panic handler
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+/// Integration test entry point
+//noinspection RsUnresolvedPath
+fn main(boot_info: &'static BootInfo) -> ! {
+    // Initializations
+    rust_os::test_init(boot_info);
+
+    test_main();
+    hlt_loop();
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_os::allocator::{self, HEAP_SIZE};
+    use rust_os::memory::walk::{self, Mapping};
+    use rust_os::memory::{self, TranslateError};
+    use x86_64::structures::paging::page_table::PageTableLevel;
+    use x86_64::structures::paging::{PageTableFlags, Translate};
+    use x86_64::{PhysAddr, VirtAddr};
+
+    /// Address of the VGA text buffer, identity-mapped by the bootloader
+    const VGA_BUFFER: u64 = 0xb8000;
+
+    #[test_case]
+    fn vga_buffer_is_mapped() {
+        let addr = VirtAddr::new(VGA_BUFFER);
+        let mapping = walk::find_mapping(addr).expect("VGA buffer not mapped");
+        assert_eq!(mapping.translate(addr), Some(PhysAddr::new(VGA_BUFFER)));
+        assert!(mapping.flags.contains(PageTableFlags::WRITABLE));
+        assert!(!mapping.flags.contains(PageTableFlags::USER_ACCESSIBLE));
+    }
+
+    #[test_case]
+    fn heap_is_mapped() {
+        for offset in [0, HEAP_SIZE - 1] {
+            let mapping =
+                walk::find_mapping(VirtAddr::new((allocator::heap_start() + offset) as u64))
+                    .expect("heap not mapped");
+            assert!(mapping.flags.contains(PageTableFlags::WRITABLE));
+        }
+    }
+
+    #[test_case]
+    fn physical_memory_is_mapped() {
+        let offset = memory::physical_memory_offset().expect("memory not initialized");
+        let mapping = walk::find_mapping(offset).expect("physical memory not mapped");
+        assert_eq!(mapping.translate(offset), Some(PhysAddr::new(0)));
+    }
+
+    #[test_case]
+    fn unmapped_address() {
+        assert_eq!(
+            walk::find_mapping(VirtAddr::new(0x0000_5555_0000_0000)),
+            None
+        );
+    }
+
+    #[test_case]
+    fn mappings_are_sorted_and_coalesced() {
+        let mut previous: Option<Mapping> = None;
+        walk::for_each_mapping(|mapping| {
+            assert!(mapping.size > 0 && mapping.size % 4096 == 0);
+            if let Some(previous) = previous {
+                let previous_end = previous.start.as_u64() + previous.size;
+                assert!(mapping.start.as_u64() >= previous_end);
+
+                // Adjacent mappings are merged unless they differ
+                assert!(
+                    mapping.start.as_u64() != previous_end
+                        || mapping.phys_start != previous.phys_start + previous.size
+                        || mapping.flags != previous.flags
+                );
+            }
+            previous = Some(*mapping);
+        });
+        assert!(previous.is_some());
+    }
+
+    #[test_case]
+    fn translate_vga_buffer() {
+        let translation = memory::translate(VirtAddr::new(VGA_BUFFER + 0x10)).expect("not mapped");
+        assert_eq!(translation.phys_addr, PhysAddr::new(VGA_BUFFER + 0x10));
+        assert!(translation.flags.contains(PageTableFlags::WRITABLE));
+    }
+
+    #[test_case]
+    fn translate_heap() {
+        let addr = VirtAddr::new((allocator::heap_start() + 0x123) as u64);
+        let translation = memory::translate(addr).expect("heap not mapped");
+        assert_eq!(translation.page_size, 4096);
+        assert!(translation.flags.contains(PageTableFlags::WRITABLE));
+
+        // The result must agree with the mapper
+        let phys_addr = memory::with_kernel_memory(|memory| memory.mapper.translate_addr(addr))
+            .expect("kernel memory not initialized");
+        assert_eq!(Some(translation.phys_addr), phys_addr);
+    }
+
+    #[test_case]
+    fn translate_huge_page() {
+        // The bootloader maps the physical memory with huge pages
+        let offset = memory::physical_memory_offset().expect("memory not initialized");
+        let translation = memory::translate(offset + 0x20_1234u64).expect("not mapped");
+        assert_eq!(translation.phys_addr, PhysAddr::new(0x20_1234));
+        assert!(translation.page_size >= 2 * 1024 * 1024);
+    }
+
+    #[test_case]
+    fn translate_not_present() {
+        // The page right after the heap shares its level 1 table
+        let heap_end = VirtAddr::new((allocator::heap_start() + allocator::heap_size()) as u64);
+        assert_eq!(
+            memory::translate(heap_end),
+            Err(TranslateError::NotPresent(PageTableLevel::One))
+        );
+        assert!(memory::translate(VirtAddr::new(0x0000_5555_0000_0000)).is_err());
+    }
+}
@@ -0,0 +1,142 @@
+//! Integration test for the CMOS real-time clock
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use core::panic::PanicInfo;
+
+use bootloader::{BootInfo, entry_point};
+use rust_os::hlt_loop;
+
+entry_point!(main);
+
+/// Panic handler
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+/// Integration test entry point
+//noinspection RsUnresolvedPath
+fn main(boot_info: &'static BootInfo) -> ! {
+    // Initializations
+    rust_os::test_init(boot_info);
+
+    test_main();
+    hlt_loop();
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::ToString;
+    use core::time::Duration;
+
+    use rust_os::interrupts;
+    use rust_os::time::rtc::{self, DateTime};
+    use rust_os::time::{self, TimeError};
+
+    /// Return the date and time with the passed fields
+    const fn date_time(
+        year: u16,
+        month: u8,
+        day: u8,
+        hour: u8,
+        minute: u8,
+        second: u8,
+    ) -> DateTime {
+        DateTime {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        }
+    }
+
+    /// Count the periodic interrupts received during the passed number of milliseconds
+    fn count_periodic(millis: u64) -> u64 {
+        let start = rtc::periodic_ticks();
+        time::sleep(Duration::from_millis(millis));
+        rtc::periodic_ticks() - start
+    }
+
+    #[test_case]
+    fn read_date_time() {
+        let now = rtc::read();
+        assert!((2000..2100).contains(&now.year));
+        assert!((1..=12).contains(&now.month));
+        assert!((1..=31).contains(&now.day));
+        assert!(now.hour < 24 && now.minute < 60 && now.second < 60);
+        assert_eq!(rtc::unix_timestamp() / 86400, now.unix_timestamp() / 86400);
+    }
+
+    #[test_case]
+    fn clock_advances() {
+        let before = rtc::unix_timestamp();
+        time::sleep(Duration::from_millis(1100));
+        let elapsed = rtc::unix_timestamp() - before;
+        assert!((1..=2).contains(&elapsed));
+    }
+
+    #[test_case]
+    fn unix_timestamps() {
+        assert_eq!(date_time(1970, 1, 1, 0, 0, 0).unix_timestamp(), 0);
+        assert_eq!(date_time(1969, 12, 31, 23, 59, 59).unix_timestamp(), 0);
+        assert_eq!(
+            date_time(1999, 12, 31, 23, 59, 59).unix_timestamp(),
+            946_684_799
+        );
+        assert_eq!(date_time(2000, 3, 1, 0, 0, 0).unix_timestamp(), 951_868_800);
+        assert_eq!(
+            date_time(2024, 2, 29, 12, 34, 56).unix_timestamp(),
+            1_709_210_096
+        );
+    }
+
+    #[test_case]
+    fn display() {
+        let date = date_time(2024, 2, 9, 7, 5, 3);
+        assert_eq!(date.to_string(), "2024-02-09T07:05:03Z");
+    }
+
+    #[test_case]
+    fn invalid_periodic_frequencies() {
+        for frequency in [0, 1, 3, 1000, 16384] {
+            assert_eq!(
+                rtc::enable_periodic_interrupt(frequency),
+                Err(TimeError::InvalidFrequency(frequency))
+            );
+        }
+    }
+
+    #[test_case]
+    fn periodic_interrupt_with_pics() {
+        rtc::enable_periodic_interrupt(1024).expect("valid frequency");
+        assert_eq!(rtc::periodic_frequency(), 1024);
+
+        // About 102 interrupts in 100 ms
+        let count = count_periodic(100);
+        assert!((90..=115).contains(&count));
+
+        // An interrupt may already be pending when the interrupt is disabled
+        rtc::disable_periodic_interrupt();
+        assert!(count_periodic(20) <= 1);
+    }
+
+    #[test_case]
+    fn periodic_interrupt_with_apic() {
+        interrupts::init_apic().expect("APIC initialization failed");
+        rtc::enable_periodic_interrupt(256).expect("valid frequency");
+
+        // About 25 interrupts in 100 ms
+        let count = count_periodic(100);
+        assert!((20..=30).contains(&count));
+        rtc::disable_periodic_interrupt();
+    }
+}
@@ -0,0 +1,138 @@
+//! Integration test for slab caches
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+extern crate alloc;
+
+use core::panic::PanicInfo;
+
+use bootloader::{BootInfo, entry_point};
+use rust_os::hlt_loop;
+
+entry_point!(main);
+
+/// Panic handler
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+/// Integration test entry point
+//noinspection RsUnresolvedPath
+fn main(boot_info: &'static BootInfo) -> ! {
+    // Initializations
+    rust_os::test_init(boot_info);
+
+    test_main();
+    hlt_loop();
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    use rust_os::allocator;
+    use rust_os::allocator::slab::SlabCache;
+
+    /// Number of dropped [`Object`] instances
+    static DROPS: AtomicUsize = AtomicUsize::new(0);
+
+    /// Test object, which counts its drops
+    struct Object {
+        id: u64,
+        data: [u8; 56],
+    }
+
+    impl Object {
+        const fn new() -> Self {
+            Self {
+                id: 0xdead_beef,
+                data: [0xaa; 56],
+            }
+        }
+    }
+
+    impl Drop for Object {
+        fn drop(&mut self) {
+            DROPS.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test_case]
+    fn constructor() {
+        let mut cache = SlabCache::new("object", Object::new);
+
+        let obj = cache.alloc().expect("slab allocation failed");
+        let object = unsafe { obj.as_ref() };
+        assert_eq!(object.id, 0xdead_beef);
+        assert!(object.data.iter().all(|&b| b == 0xaa));
+
+        let drops = DROPS.load(Ordering::Relaxed);
+        unsafe { cache.free(obj) };
+        assert_eq!(DROPS.load(Ordering::Relaxed), drops + 1);
+    }
+
+    #[test_case]
+    fn distinct_aligned_objects() {
+        let mut cache = SlabCache::new("u128", || 0u128);
+        let objects_per_slab = cache.stats().objects_per_slab;
+
+        let objects: Vec<_> = (0..objects_per_slab * 3)
+            .map(|i| cache.alloc_with(i as u128).expect("slab allocation failed"))
+            .collect();
+        for (i, obj) in objects.iter().enumerate() {
+            assert!(obj.as_ptr().is_aligned());
+            assert_eq!(unsafe { *obj.as_ptr() }, i as u128);
+        }
+        assert_eq!(cache.stats().slabs, 3);
+
+        for obj in objects {
+            unsafe { cache.free(obj) };
+        }
+    }
+
+    #[test_case]
+    fn empty_slabs_are_released() {
+        let before = allocator::stats();
+        let mut cache = SlabCache::new("object", Object::new);
+        let objects_per_slab = cache.stats().objects_per_slab;
+
+        let objects: Vec<_> = (0..objects_per_slab * 4)
+            .map(|_| cache.alloc().expect("slab allocation failed"))
+            .collect();
+        let stats = cache.stats();
+        assert_eq!(stats.slabs, 4);
+        assert_eq!(stats.objects_in_use, objects_per_slab * 4);
+        assert_eq!(stats.objects_free, 0);
+
+        // Only one empty slab is kept once all objects are freed
+        for obj in objects {
+            unsafe { cache.free(obj) };
+        }
+        let stats = cache.stats();
+        assert_eq!(stats.slabs, 1);
+        assert_eq!(stats.allocations, stats.frees);
+
+        assert_eq!(cache.shrink(), 1);
+        assert_eq!(cache.stats().slabs, 0);
+        drop(cache);
+        allocator::assert_no_leaks(&before);
+    }
+
+    #[test_case]
+    fn large_objects() {
+        let mut cache = SlabCache::new("large", || [0u8; 3000]);
+        let stats = cache.stats();
+        assert!(stats.slab_size.is_power_of_two());
+        assert!(stats.objects_per_slab >= 8);
+
+        let obj = cache.alloc().expect("slab allocation failed");
+        assert!(obj.as_ptr().is_aligned());
+        unsafe { cache.free(obj) };
+    }
+}
@@ -8,7 +8,10 @@ use core::panic::PanicInfo;
 
 use bootloader::{BootInfo, entry_point};
 use lazy_static::lazy_static;
-use rust_os::{QemuExitCode, exit_qemu, hlt_loop, serial_print, serial_println};
+use rust_os::memory::buddy::BuddyFrameAllocator;
+use rust_os::memory::stack;
+use rust_os::{QemuExitCode, exit_qemu, hlt_loop, memory, serial_print, serial_println};
+use x86_64::VirtAddr;
 use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
 
 lazy_static! {
@@ -43,13 +46,22 @@ fn panic(info: &PanicInfo) -> ! {
 
 /// Integration test entry point
 #[allow(clippy::missing_panics_doc)]
-fn main(_boot_info: &'static BootInfo) -> ! {
+fn main(boot_info: &'static BootInfo) -> ! {
     serial_print!("stack_overflow::stack_overflow... ");
 
     // Initialize the OS with a custom IDT
     rust_os::gdt::init();
     init_test_idt();
 
+    // Move the interrupt stacks to guarded stacks
+    let phys_mem_offset = VirtAddr::new(boot_info.physical_memory_offset);
+    let mapper = unsafe { memory::init(phys_mem_offset) };
+    let frame_allocator =
+        unsafe { BuddyFrameAllocator::init(&boot_info.memory_map, phys_mem_offset) }
+            .expect("frame allocator initialization failed");
+    memory::init_kernel_memory(mapper, frame_allocator);
+    rust_os::gdt::init_interrupt_stacks().expect("interrupt stack allocation failed");
+
     // Trigger a kernel stack overflow
     stack_overflow();
 
@@ -69,6 +81,14 @@ extern "x86-interrupt" fn test_double_fault_handler(
     _stack_frame: InterruptStackFrame,
     _error_code: u64,
 ) -> ! {
+    // The handler must run on a guarded interrupt stack
+    let marker = 0u8;
+    let marker = VirtAddr::from_ptr(&raw const marker);
+    if !stack::stack_region().is_some_and(|region| region.contains(marker)) {
+        serial_println!("[double fault handler not on a guarded stack]");
+        exit_qemu(QemuExitCode::Failure);
+    }
+
     serial_println!("[ok]");
     exit_qemu(QemuExitCode::Success);
     hlt_loop();
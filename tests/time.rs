@@ -0,0 +1,90 @@
+//! Integration test for the timer tick counter and uptime
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+
+use bootloader::{BootInfo, entry_point};
+use rust_os::hlt_loop;
+
+entry_point!(main);
+
+/// Panic handler
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+/// Integration test entry point
+//noinspection RsUnresolvedPath
+fn main(boot_info: &'static BootInfo) -> ! {
+    // Initializations
+    rust_os::test_init(boot_info);
+
+    test_main();
+    hlt_loop();
+}
+
+#[cfg(test)]
+mod tests {
+    use core::time::Duration;
+
+    use rust_os::time::{self, DEFAULT_FREQUENCY, TimeError};
+    use x86_64::instructions::hlt;
+
+    /// Halt until the passed number of ticks have elapsed, returning the uptime before and after
+    fn wait_ticks(n: u64) -> (Duration, Duration) {
+        let start = time::ticks();
+        let before = time::uptime();
+        while time::ticks() - start < n {
+            hlt();
+        }
+        (before, time::uptime())
+    }
+
+    #[test_case]
+    fn default_frequency() {
+        assert_eq!(time::frequency(), DEFAULT_FREQUENCY);
+    }
+
+    #[test_case]
+    fn ticks_advance() {
+        let (before, after) = wait_ticks(100);
+        assert!(after > before);
+
+        // 100 ticks at 1 kHz are about 100 ms, give or take the ticks at both ends
+        let elapsed = after.saturating_sub(before);
+        assert!(elapsed >= Duration::from_millis(98) && elapsed <= Duration::from_millis(102));
+    }
+
+    #[test_case]
+    fn change_frequency() {
+        assert_eq!(time::set_frequency(100), Ok(100));
+        assert_eq!(time::frequency(), 100);
+        let (before, after) = wait_ticks(10);
+        let elapsed = after.saturating_sub(before);
+        assert!(elapsed >= Duration::from_millis(90) && elapsed <= Duration::from_millis(110));
+
+        // The uptime stays monotonic when the frequency changes back
+        let before = time::uptime();
+        assert_eq!(
+            time::set_frequency(DEFAULT_FREQUENCY),
+            Ok(DEFAULT_FREQUENCY)
+        );
+        assert!(time::uptime() >= before);
+    }
+
+    #[test_case]
+    fn invalid_frequencies() {
+        for frequency in [0, 18, 2_000_000] {
+            assert_eq!(
+                time::set_frequency(frequency),
+                Err(TimeError::InvalidFrequency(frequency))
+            );
+        }
+    }
+}
@@ -0,0 +1,156 @@
+//! Integration test for sleeping and timer callbacks
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+
+use bootloader::{BootInfo, entry_point};
+use rust_os::hlt_loop;
+
+entry_point!(main);
+
+/// Panic handler
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+/// Integration test entry point
+//noinspection RsUnresolvedPath
+fn main(boot_info: &'static BootInfo) -> ! {
+    // Initializations
+    rust_os::test_init(boot_info);
+
+    test_main();
+    hlt_loop();
+}
+
+#[cfg(test)]
+mod tests {
+    use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+    use core::time::Duration;
+
+    use rust_os::time::timer::{self, MAX_TIMERS};
+    use rust_os::time::{self, TimeError};
+    use x86_64::instructions::hlt;
+
+    /// Tick at which [`record_tick`] ran
+    static FIRED_AT: AtomicU64 = AtomicU64::new(0);
+    /// Number of times [`count`] ran
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+    /// Order in which [`first`] and [`second`] ran
+    static ORDER: AtomicUsize = AtomicUsize::new(0);
+    /// Number of times [`rearm`] still has to run
+    static REARMS: AtomicUsize = AtomicUsize::new(0);
+
+    fn record_tick() {
+        FIRED_AT.store(time::ticks(), Ordering::Relaxed);
+    }
+
+    fn count() {
+        COUNT.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn first() {
+        ORDER.store(ORDER.load(Ordering::Relaxed) * 10 + 1, Ordering::Relaxed);
+    }
+
+    fn second() {
+        ORDER.store(ORDER.load(Ordering::Relaxed) * 10 + 2, Ordering::Relaxed);
+    }
+
+    fn rearm() {
+        if REARMS.fetch_sub(1, Ordering::Relaxed) > 1 {
+            time::schedule_after(Duration::from_millis(1), rearm).expect("timer queue is full");
+        }
+    }
+
+    /// Halt until the passed condition holds
+    fn wait_until(condition: impl Fn() -> bool) {
+        while !condition() {
+            hlt();
+        }
+    }
+
+    #[test_case]
+    fn sleep_waits() {
+        // At 1 kHz, sleeping 50 ms takes 50 ticks, plus at most one for the tick in progress
+        let start = time::ticks();
+        time::sleep(Duration::from_millis(50));
+        let elapsed = time::ticks() - start;
+        assert!((50..=51).contains(&elapsed));
+
+        let start = time::ticks();
+        time::sleep(Duration::ZERO);
+        assert!(time::ticks() - start <= 1);
+    }
+
+    #[test_case]
+    fn timer_fires_after_delay() {
+        let start = time::ticks();
+        time::schedule_after(Duration::from_millis(20), record_tick).expect("timer queue is full");
+        assert_eq!(timer::pending(), 1);
+        wait_until(|| FIRED_AT.load(Ordering::Relaxed) != 0);
+
+        let elapsed = FIRED_AT.load(Ordering::Relaxed) - start;
+        assert!((20..=21).contains(&elapsed));
+        assert_eq!(timer::pending(), 0);
+    }
+
+    #[test_case]
+    fn zero_delay_fires_on_next_tick() {
+        COUNT.store(0, Ordering::Relaxed);
+        time::schedule_after(Duration::ZERO, count).expect("timer queue is full");
+        let start = time::ticks();
+        wait_until(|| COUNT.load(Ordering::Relaxed) == 1);
+        assert!(time::ticks() - start <= 2);
+    }
+
+    #[test_case]
+    fn timers_fire_in_deadline_order() {
+        ORDER.store(0, Ordering::Relaxed);
+        time::schedule_after(Duration::from_millis(10), second).expect("timer queue is full");
+        time::schedule_after(Duration::from_millis(5), first).expect("timer queue is full");
+        wait_until(|| timer::pending() == 0);
+        assert_eq!(ORDER.load(Ordering::Relaxed), 12);
+    }
+
+    #[test_case]
+    fn cancel_timer() {
+        COUNT.store(0, Ordering::Relaxed);
+        let id =
+            time::schedule_after(Duration::from_millis(5), count).expect("timer queue is full");
+        assert!(timer::cancel(id));
+        assert!(!timer::cancel(id));
+        time::sleep(Duration::from_millis(10));
+        assert_eq!(COUNT.load(Ordering::Relaxed), 0);
+    }
+
+    #[test_case]
+    fn callback_reschedules() {
+        REARMS.store(5, Ordering::Relaxed);
+        let start = time::ticks();
+        time::schedule_after(Duration::from_millis(1), rearm).expect("timer queue is full");
+        wait_until(|| REARMS.load(Ordering::Relaxed) == 0);
+        assert!(time::ticks() - start >= 5);
+        assert_eq!(timer::pending(), 0);
+    }
+
+    #[test_case]
+    fn queue_full() {
+        COUNT.store(0, Ordering::Relaxed);
+        for _ in 0..MAX_TIMERS {
+            time::schedule_after(Duration::from_millis(1), count).expect("timer queue is full");
+        }
+        assert_eq!(
+            time::schedule_after(Duration::from_millis(1), count),
+            Err(TimeError::TooManyTimers)
+        );
+        wait_until(|| timer::pending() == 0);
+        assert_eq!(COUNT.load(Ordering::Relaxed), MAX_TIMERS);
+    }
+}
@@ -0,0 +1,105 @@
+//! Integration test for SMEP, SMAP and the user memory accessors
+
+#![no_std]
+#![no_main]
+#![feature(custom_test_frameworks)]
+#![test_runner(rust_os::test_runner)]
+#![reexport_test_harness_main = "test_main"]
+
+use core::panic::PanicInfo;
+
+use bootloader::{BootInfo, entry_point};
+use rust_os::hlt_loop;
+
+entry_point!(main);
+
+/// Panic handler
+#[panic_handler]
+fn panic(info: &PanicInfo) -> ! {
+    rust_os::test_panic_handler(info)
+}
+
+/// Integration test entry point
+//noinspection RsUnresolvedPath
+fn main(boot_info: &'static BootInfo) -> ! {
+    // Initializations
+    rust_os::test_init(boot_info);
+
+    test_main();
+    hlt_loop();
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_os::memory::region::{self, Backing, RegionKind};
+    use rust_os::memory::user::{self, UserCopyError};
+    use rust_os::memory::{self, protect};
+    use x86_64::VirtAddr;
+    use x86_64::registers::control::{Cr4, Cr4Flags};
+    use x86_64::structures::paging::PageTableFlags;
+
+    #[test_case]
+    fn smep_smap_enabled() {
+        let cr4 = Cr4::read();
+        assert_eq!(
+            cr4.contains(Cr4Flags::SUPERVISOR_MODE_EXECUTION_PROTECTION),
+            protect::supports_smep()
+        );
+        assert_eq!(
+            cr4.contains(Cr4Flags::SUPERVISOR_MODE_ACCESS_PREVENTION),
+            protect::supports_smap()
+        );
+    }
+
+    #[test_case]
+    fn copy_roundtrip() {
+        let flags =
+            PageTableFlags::PRESENT | PageTableFlags::WRITABLE | PageTableFlags::USER_ACCESSIBLE;
+        let region = region::allocate(
+            "user",
+            0x2000,
+            0x1000,
+            RegionKind::User,
+            Backing::OnDemand(flags),
+        )
+        .expect("region allocation failed");
+
+        // Copy across the page boundary, mapping both pages on demand
+        let addr = region.start + 0xff0u64;
+        let data = *b"user memory roundtrip";
+        user::copy_to_user(addr, &data).expect("copy to user failed");
+        let translation = memory::translate(addr).expect("user page not mapped");
+        assert!(translation.flags.contains(PageTableFlags::USER_ACCESSIBLE));
+
+        let mut buf = [0; 21];
+        user::copy_from_user(&mut buf, addr).expect("copy from user failed");
+        assert_eq!(buf, data);
+    }
+
+    #[test_case]
+    fn copy_recovers_from_faults() {
+        let region = region::allocate("user", 0x1000, 0x1000, RegionKind::User, Backing::Eager)
+            .expect("region allocation failed");
+
+        // The region is not mapped, so the copy faults on the first byte
+        let mut buf = [0; 8];
+        assert_eq!(
+            user::copy_from_user(&mut buf, region.start),
+            Err(UserCopyError::Fault { copied: 0 })
+        );
+        assert_eq!(
+            user::copy_to_user(region.start, &buf),
+            Err(UserCopyError::Fault { copied: 0 })
+        );
+    }
+
+    #[test_case]
+    fn copy_rejects_kernel_memory() {
+        let kernel_data = [0u8; 8];
+        let mut buf = [0; 8];
+        assert_eq!(
+            user::copy_from_user(&mut buf, VirtAddr::from_ptr(&raw const kernel_data)),
+            Err(UserCopyError::InvalidRange)
+        );
+    }
+}